@@ -0,0 +1,82 @@
+//! Compares heap allocations of element clones with a shared name (`Arc<str>`) against clones of the same
+//! element with an owned `String` name, as elements used to be stored
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fert_calc::elements::{Element, KnownElements};
+use std::{
+	alloc::{GlobalAlloc, Layout, System},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+const ELEMENTS: &str = include_str!("../elements.toml");
+/// How many clones are made to count allocations
+const CLONES_COUNT: usize = 1000;
+
+// Counts all heap allocations made by the benchmark
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Element with an owned name, the layout before names were shared
+#[derive(Clone)]
+struct OwnedNameElement {
+	_molar_mass: f64,
+	_name: String,
+	_insignificant: Option<bool>,
+	_priority: Option<u32>,
+	_aliases: Option<Vec<String>>,
+	_group: Option<String>,
+}
+
+impl From<&Element> for OwnedNameElement {
+	fn from(elt: &Element) -> Self {
+		OwnedNameElement {
+			_molar_mass: elt.molar_mass,
+			_name: elt.name.to_string(),
+			_insignificant: elt.insignificant,
+			_priority: elt.priority,
+			_aliases: elt.aliases.clone(),
+			_group: elt.group.clone(),
+		}
+	}
+}
+
+// Returns the number of allocations made by the closure
+fn count_allocations<T>(f: impl FnOnce() -> T) -> usize {
+	let before = ALLOCATIONS.load(Ordering::Relaxed);
+	black_box(f());
+	ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn element_clones(c: &mut Criterion) {
+	let known_elements = KnownElements::new_with_string(ELEMENTS).unwrap();
+	let shared = known_elements.elements["N"].clone();
+	let owned = OwnedNameElement::from(&shared);
+
+	let shared_allocations = count_allocations(|| (0..CLONES_COUNT).map(|_| shared.clone()).collect::<Vec<_>>());
+	let owned_allocations = count_allocations(|| (0..CLONES_COUNT).map(|_| owned.clone()).collect::<Vec<_>>());
+	println!(
+		"allocations per {} element clones: shared name {}, owned name {}",
+		CLONES_COUNT, shared_allocations, owned_allocations
+	);
+
+	c.bench_function("element_clone_shared_name", |b| b.iter(|| black_box(&shared).clone()));
+	c.bench_function("element_clone_owned_name", |b| b.iter(|| black_box(&owned).clone()));
+}
+
+criterion_group!(benches, element_clones);
+criterion_main!(benches);
@@ -0,0 +1,37 @@
+//! Performance baseline for the hot paths of the calculations
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fert_calc::{
+	compound::Compound, concentration::DryDosing, elements::KnownElements, tank::Tank, DiluteMethod, FertilizersDb,
+};
+
+const ELEMENTS: &str = include_str!("../elements.toml");
+const FERTILIZERS: &str = include_str!("../fertilizers.toml");
+
+fn load_fertilizers(known_elements: &KnownElements) -> FertilizersDb {
+	let mut fertilizers_db: FertilizersDb = Default::default();
+	fertilizers_db.load_db(FERTILIZERS, known_elements).unwrap();
+	fertilizers_db
+}
+
+fn calculations(c: &mut Criterion) {
+	let known_elements = KnownElements::new_with_string(ELEMENTS).unwrap();
+	let fertilizers_db = load_fertilizers(&known_elements);
+	// A mix with 10 elements
+	let mix = &fertilizers_db.known_fertilizers["Solufeed High K"];
+	let compound = Compound::new("KNO3", &known_elements).unwrap();
+	let tank = Tank::new_from_toml("volume = 200").unwrap();
+	let dosing = DryDosing::with_dose(2.5);
+
+	c.bench_function("compound_new", |b| {
+		b.iter(|| Compound::new(black_box("Ca(NO3)2*4H2O"), &known_elements).unwrap())
+	});
+	c.bench_function("components_percentage", |b| b.iter(|| black_box(mix).components_percentage(&known_elements)));
+	c.bench_function("dry_dilute", |b| {
+		b.iter(|| dosing.dilute(black_box(&compound), &known_elements, black_box(&tank)).unwrap())
+	});
+	c.bench_function("load_db", |b| b.iter(|| load_fertilizers(black_box(&known_elements))));
+}
+
+criterion_group!(benches, calculations);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use std::{
+	env, fs,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+// Exports build information for `--build-info`
+fn main() {
+	// Explicit rerun conditions disable the default ones, so sources are listed to keep the timestamp fresh
+	println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+	println!("cargo:rerun-if-changed=Cargo.toml");
+	println!("cargo:rerun-if-changed=src");
+
+	// Respect reproducible builds if the timestamp is specified explicitly
+	let timestamp = env::var("SOURCE_DATE_EPOCH")
+		.ok()
+		.and_then(|epoch| epoch.parse::<u64>().ok())
+		.unwrap_or_else(|| {
+			SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|elapsed| elapsed.as_secs())
+				.unwrap_or_default()
+		});
+	println!("cargo:rustc-env=FERT_CALC_BUILD_TIMESTAMP={}", timestamp);
+
+	// Cargo does not export the edition to build scripts, so it is read from the manifest
+	let manifest = fs::read_to_string(format!("{}/Cargo.toml", env::var("CARGO_MANIFEST_DIR").unwrap_or_default()))
+		.unwrap_or_default();
+	let edition = manifest
+		.parse::<toml::Table>()
+		.ok()
+		.and_then(|manifest| manifest.get("package")?.get("edition")?.as_str().map(str::to_owned))
+		// Cargo defaults to the 2015 edition if it is not specified
+		.unwrap_or_else(|| "2015".to_owned());
+	println!("cargo:rustc-env=FERT_CALC_EDITION={}", edition);
+}
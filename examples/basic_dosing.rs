@@ -0,0 +1,20 @@
+//! Concentration of elements after adding a dry dose of KNO3 to a tank
+
+use anyhow::Result;
+use fert_calc::{
+	compound::Compound, concentration::DryDosing, elements::GLOBAL_KNOWN_ELEMENTS, tank::Tank, DiluteMethod,
+};
+
+fn main() -> Result<()> {
+	let known_elements = &*GLOBAL_KNOWN_ELEMENTS;
+	let compound = Compound::new("KNO3", known_elements)?;
+	let tank = Tank::new_from_toml("volume = 200")?;
+	let dosing = DryDosing::with_dose(2.5);
+	dosing.validate()?;
+
+	let result = dosing.dilute(&compound, known_elements, &tank)?;
+	println!("{} of {}, {:?}", dosing, compound, tank);
+	println!("{}", result);
+
+	Ok(())
+}
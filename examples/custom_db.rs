@@ -0,0 +1,39 @@
+//! Loads a custom fertilizers database and prints the composition of each fertilizer
+
+use anyhow::Result;
+use fert_calc::{elements::GLOBAL_KNOWN_ELEMENTS, FertilizersDb};
+
+const CUSTOM_DB: &str = r#"
+[meta]
+version = "1"
+author = "Example"
+description = "A custom fertilizers database"
+
+["Tomato feed".compounds]
+N = 4
+P2O5 = 2
+K2O = 6
+
+["Epsom salt"]
+formula = "MgSO4*7H2O"
+"#;
+
+fn main() -> Result<()> {
+	let known_elements = &*GLOBAL_KNOWN_ELEMENTS;
+	let mut fertilizers_db: FertilizersDb = Default::default();
+	fertilizers_db.load_db(CUSTOM_DB, known_elements)?;
+
+	if let Some(metadata) = fertilizers_db.metadata() {
+		println!("Database: {}", metadata);
+	}
+
+	for (name, fertilizer) in fertilizers_db.known_fertilizers.iter() {
+		println!("{}:", name);
+
+		for component in fertilizer.components_percentage(known_elements) {
+			println!("  {:?}", component);
+		}
+	}
+
+	Ok(())
+}
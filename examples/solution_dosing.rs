@@ -0,0 +1,21 @@
+//! How much KH2PO4 should be dissolved in a stock solution to reach the target phosphate level
+
+use anyhow::Result;
+use fert_calc::{
+	compound::Compound, concentration::SolutionDosing, elements::GLOBAL_KNOWN_ELEMENTS, tank::Tank, DiluteMethod,
+};
+
+fn main() -> Result<()> {
+	let known_elements = &*GLOBAL_KNOWN_ELEMENTS;
+	let compound = Compound::new("KH2PO4", known_elements)?;
+	let tank = Tank::new_from_toml("volume = 120")?;
+	// 1 mg/L of PO4 with each 10 mL portion from a 500 mL container
+	let dosing = SolutionDosing::with_target("PO4", 1.0).with_container(500.0, 10.0);
+	dosing.validate()?;
+
+	let result = dosing.dilute(&compound, known_elements, &tank)?;
+	println!("{} of {}, {:?}", dosing, compound, tank);
+	println!("{}", result);
+
+	Ok(())
+}
@@ -1,18 +1,34 @@
 use crate::{
 	concentration::{ElementConcentrationAlias, ElementsConcentrationsWithAliases},
 	elements::*,
-	traits::Fertilizer,
+	traits::{Fertilizer, FertilizerKind},
 };
 use accurate::{sum::Sum2, traits::*};
 use anyhow::{anyhow, Result};
 use rustyline::Editor;
 
 use itertools::Itertools;
+use serde::Serialize;
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, HashMap},
 	fmt::{Debug, Display, Formatter},
+	hash::{Hash, Hasher},
+	str::FromStr,
 };
 
+/// How many times a user can retry the compound input
+const MAX_INPUT_ATTEMPTS: u32 = 3;
+/// Atoms count that is suspicious and likely a typo
+const SUSPICIOUS_ATOMS_COUNT: u32 = 50;
+/// Atoms count that is rejected by the parser
+const MAX_ATOMS_COUNT: u32 = 1000;
+/// Characters that separate a hydrate part of the formula: asterisk, interpunct and bullet operator
+const HYDRATE_SEPARATORS: [char; 3] = ['*', '\u{00B7}', '\u{2219}'];
+/// Opening brackets of a sub-compound, square brackets are used for coordination complexes
+const OPENING_BRACKETS: [char; 2] = ['(', '['];
+/// Closing brackets of a sub-compound
+const CLOSING_BRACKETS: [char; 2] = [')', ']'];
+
 /// A structure that represents a molecule of some compound
 #[derive(Debug, Default, Clone)]
 pub struct Compound {
@@ -22,32 +38,109 @@ pub struct Compound {
 	pub name: String,
 }
 
+// Serialized form of a compound, elements are represented by their symbols
+#[derive(Serialize)]
+struct CompoundData<'a> {
+	name: &'a str,
+	elements: BTreeMap<&'a str, u32>,
+}
+
+impl Serialize for Compound {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		CompoundData { name: self.name.as_str(), elements: self.canonical_composition().into_iter().collect() }
+			.serialize(serializer)
+	}
+}
+
 impl Display for Compound {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", &self.name)
 	}
 }
 
+// Compounds are compared by their composition, so `KNO3` and `NO3K` are the same compound
 impl PartialEq for Compound {
 	fn eq(&self, other: &Self) -> bool {
-		self.name == other.name
+		self.canonical_composition() == other.canonical_composition()
 	}
 }
 
 impl Eq for Compound {}
 
+impl Hash for Compound {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.canonical_composition().hash(state)
+	}
+}
+
 impl Compound {
 	fn process_acc<'a>(&mut self, acc: &str, cnt: u32, known_elts: &'a KnownElements) -> Result<&'a Element> {
 		let maybe_elt = known_elts.elements.get(acc);
 
 		if let Some(elt) = maybe_elt {
-			*self.elements.entry(elt.clone()).or_default() += cnt;
+			self.add_atoms(elt, cnt)?;
 			return Ok(elt)
 		}
 
 		Err(anyhow!("Unknown element: {}", acc))
 	}
 
+	// Adds atoms of an element failing as soon as the count exceeds the sane limit
+	fn add_atoms(&mut self, elt: &Element, cnt: u32) -> Result<()> {
+		let total = self.elements.entry(elt.clone()).or_default();
+		*total = total
+			.checked_add(cnt)
+			.filter(|total| *total <= MAX_ATOMS_COUNT)
+			.ok_or_else(|| anyhow!("Too many atoms of {} in {}", elt.name, self.name))?;
+
+		Ok(())
+	}
+
+	// Adds all atoms of a sub-compound repeated `mult` times
+	fn add_subcompound(&mut self, subcompound: &Compound, mult: u32) -> Result<()> {
+		for (elt, sub_cnt) in subcompound.elements.iter() {
+			let cnt = sub_cnt
+				.checked_mul(mult)
+				.ok_or_else(|| anyhow!("Too many atoms of {} in {}", elt.name, self.name))?;
+			self.add_atoms(elt, cnt)?;
+		}
+
+		Ok(())
+	}
+
+	// Elements and their counts sorted by element name
+	fn canonical_composition(&self) -> Vec<(&str, u32)> {
+		self.elements
+			.iter()
+			.map(|(elt, cnt)| (elt.name.as_ref(), *cnt))
+			.sorted()
+			.collect()
+	}
+
+	/// Serializes compound to JSON as its name and atoms count by element
+	pub fn to_json(&self) -> Result<String> {
+		Ok(serde_json::to_string(self)?)
+	}
+
+	/// Serializes compound to TOML as its name and atoms count by element
+	pub fn to_toml(&self) -> Result<String> {
+		Ok(toml::to_string(self)?)
+	}
+
+	// Sanity check for the parsed atoms count
+	fn check_atoms_count(&self) -> Result<()> {
+		for (elt, cnt) in self.elements.iter() {
+			if *cnt > MAX_ATOMS_COUNT {
+				return Err(anyhow!("Too many atoms of {} in {}: {}", elt.name, self.name, cnt))
+			}
+			if *cnt > SUSPICIOUS_ATOMS_COUNT {
+				log::warn!("unusually high count of {} in {}: {}, possible typo", elt.name, self.name, cnt);
+			}
+		}
+
+		Ok(())
+	}
+
 	fn process_trail(
 		&mut self,
 		last_cnt: Option<u32>,
@@ -56,20 +149,20 @@ impl Compound {
 	) -> Result<bool> {
 		if let Some(cnt) = last_cnt {
 			return if let Some(subcompound) = last_subcompound {
-				subcompound.elements.iter().for_each(|(elt, sub_cnt)| {
-					*self.elements.entry(elt.clone()).or_default() += sub_cnt * cnt;
-				});
+				self.add_subcompound(subcompound, cnt)?;
 				Ok(true)
 			} else if let Some(last_elt) = last_element {
-				*self.elements.entry(last_elt.clone()).or_default() += cnt - 1;
+				// One atom has been already added when the element was parsed
+				let extra_cnt = cnt
+					.checked_sub(1)
+					.ok_or_else(|| anyhow!("zero atoms count of {}", last_elt.name))?;
+				self.add_atoms(last_elt, extra_cnt)?;
 				Ok(true)
 			} else {
 				Err(anyhow!("digit without element found"))
 			}
 		} else if let Some(subcompound) = last_subcompound {
-			subcompound.elements.iter().for_each(|(elt, sub_cnt)| {
-				*self.elements.entry(elt.clone()).or_default() += sub_cnt;
-			});
+			self.add_subcompound(subcompound, 1)?;
 			return Ok(true)
 		}
 
@@ -93,30 +186,36 @@ impl Compound {
 
 	/// Parses formula from a trivial string knowing some elements
 	pub fn new(formula: &str, known_elts: &KnownElements) -> Result<Self> {
+		// Compound name is taken from the formula, so it must not be blank
+		if formula.trim().is_empty() {
+			return Err(anyhow!("Empty compound name"))
+		}
+
 		let mut acc = String::new();
 		let mut new_compound: Self = Default::default();
 		let mut last_element: Option<&Element> = Default::default();
 		let mut last_subcompound: Option<Compound> = Default::default();
 		let mut last_cnt: Option<u32> = Default::default();
-		let mut obraces = 0;
-		let mut ebraces = 0;
-		new_compound.name = formula.to_owned();
-
-		for (pos, chr) in formula.chars().enumerate() {
-			if obraces > 0 {
-				if chr == ')' {
-					ebraces += 1;
-				} else if chr == '(' {
-					obraces += 1;
+		// Opening brackets of the current sub-compound, each closing bracket must match the last one
+		let mut brackets: Vec<char> = Vec::new();
+		new_compound.name = formula.trim().to_owned();
+
+		for (pos, chr) in formula.char_indices() {
+			if let Some(&opening) = brackets.last() {
+				if OPENING_BRACKETS.contains(&chr) {
+					brackets.push(chr);
+				} else if let Some(idx) = CLOSING_BRACKETS.iter().position(|closing| *closing == chr) {
+					if OPENING_BRACKETS[idx] != opening {
+						return Err(anyhow!("Mismatched brackets in {}: '{}' is closed by '{}'", formula, opening, chr))
+					}
+					brackets.pop();
 				}
 
-				if ebraces != obraces {
+				if !brackets.is_empty() {
 					acc.push(chr);
 				} else {
 					// Here, acc has the whole matching sub-compound
 					last_subcompound = Some(Compound::new(acc.as_str(), known_elts)?);
-					obraces = 0;
-					ebraces = 0;
 					acc.clear();
 				}
 			} else if chr.is_ascii_uppercase() {
@@ -146,11 +245,17 @@ impl Compound {
 				}
 				let cnt = chr.to_digit(10).unwrap();
 
-				last_cnt = match last_cnt {
-					Some(x) => Some(x * 10 + cnt),
+				let new_cnt = match last_cnt {
+					Some(x) => x.checked_mul(10).and_then(|x| x.checked_add(cnt)),
 					_ => Some(cnt),
 				};
-			} else if chr == '(' {
+				// Bail out early to avoid overflow on absurdly long numbers
+				last_cnt = Some(
+					new_cnt
+						.filter(|cnt| *cnt <= MAX_ATOMS_COUNT)
+						.ok_or_else(|| anyhow!("Too many atoms in {}", formula))?,
+				);
+			} else if OPENING_BRACKETS.contains(&chr) {
 				if !new_compound.process_trail(last_cnt, last_element, &last_subcompound)? && !acc.is_empty() {
 					new_compound.process_acc(acc.as_str(), 1, known_elts)?;
 				}
@@ -158,21 +263,25 @@ impl Compound {
 				last_element = None;
 				last_cnt = None;
 				last_subcompound = None;
-				obraces += 1;
-			} else if chr == '*' {
+				brackets.push(chr);
+			} else if CLOSING_BRACKETS.contains(&chr) {
+				return Err(anyhow!("Unmatched closing bracket '{}' in {}", chr, formula))
+			} else if HYDRATE_SEPARATORS.contains(&chr) {
 				// Hydrate addition
-				let hydrate = Compound::new_hydrate(&formula[pos + 1..], known_elts)?;
+				let hydrate = Compound::new_hydrate(&formula[pos + chr.len_utf8()..], known_elts)?;
 				// Add hydrate definition to the original formula, as we need that
 				// to calculate molecular mass
-				hydrate.elements.iter().for_each(|(elt, cnt)| {
-					*new_compound.elements.entry(elt.clone()).or_default() += cnt;
-				});
+				new_compound.add_subcompound(&hydrate, 1)?;
 				break // Stop parsing, as the rest is hydrate
 			} else {
 				// Ignore garbage stuff
 			}
 		}
 
+		if let Some(opening) = brackets.last() {
+			return Err(anyhow!("Unclosed bracket '{}' in {}", opening, formula))
+		}
+
 		// Process trail
 		if !new_compound.process_trail(last_cnt, last_element, &last_subcompound)? && !acc.is_empty() {
 			new_compound.process_acc(acc.as_str(), 1, known_elts)?;
@@ -182,19 +291,34 @@ impl Compound {
 			return Err(anyhow!("Empty compound"))
 		}
 
+		new_compound.check_atoms_count()?;
+
 		Ok(new_compound)
 	}
 
-	/// Returns a compound from stdin
+	/// Returns a compound from stdin, re-asking the user if the formula cannot be parsed
 	pub fn new_from_stdin<T: rustyline::Helper>(
 		known_elts: &KnownElements,
 		editor: &mut Editor<T, rustyline::history::DefaultHistory>,
 	) -> Result<Self> {
-		let input_compound: String = editor.readline("Input compound (e.g. KNO3): ")?;
-		Compound::new(input_compound.as_str(), known_elts)
+		let mut attempt = 1;
+
+		loop {
+			let input_compound: String = editor.readline("Input compound (e.g. KNO3): ")?;
+
+			match Compound::new(input_compound.as_str(), known_elts) {
+				Ok(compound) => return Ok(compound),
+				Err(e) if attempt < MAX_INPUT_ATTEMPTS => {
+					eprintln!("Invalid compound {}: {}, please try again", input_compound, e);
+					attempt += 1;
+				},
+				Err(e) => return Err(e),
+			}
+		}
 	}
 
 	/// Returns a molar mass for the compound
+	#[must_use]
 	pub fn molar_mass(&self) -> f64 {
 		self.elements
 			.iter()
@@ -202,6 +326,17 @@ impl Compound {
 			.sum()
 	}
 
+	/// Returns molar mass or an error if it is not positive, e.g. due to broken elements database
+	pub fn molar_mass_validated(&self) -> Result<f64> {
+		let molar_mass = self.molar_mass();
+
+		if molar_mass.is_nan() || molar_mass <= 0.0 {
+			return Err(anyhow!("invalid molar mass of {}: {}", self.name, molar_mass))
+		}
+
+		Ok(molar_mass)
+	}
+
 	/// Returns percentage for a specific element
 	pub fn element_fraction(&self, element: &Element) -> Option<f64> {
 		let molar_mass = self.molar_mass();
@@ -212,14 +347,34 @@ impl Compound {
 	}
 }
 
-impl Fertilizer for Compound {
-	/// Returns elements percentage for all elements except unimportant
-	fn components_percentage(&self, known_elts: &KnownElements) -> Vec<ElementsConcentrationsWithAliases> {
-		let molar_mass = self.molar_mass();
+// Parses a compound using the embedded elements database
+impl FromStr for Compound {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		Compound::new(s, &GLOBAL_KNOWN_ELEMENTS)
+	}
+}
+
+impl Compound {
+	// Returns components percentage optionally including insignificant elements
+	fn components_percentage_filtered(
+		&self,
+		known_elts: &KnownElements,
+		include_insignificant: bool,
+	) -> Vec<ElementsConcentrationsWithAliases> {
+		// Percentages make no sense without a valid molar mass
+		let molar_mass = match self.molar_mass_validated() {
+			Ok(molar_mass) => molar_mass,
+			Err(err) => {
+				log::warn!("{}", err);
+				return Vec::new()
+			},
+		};
 
 		self.elements
 			.iter()
-			.filter(|(element, _)| !element.is_insignificant())
+			.filter(|(element, _)| include_insignificant || !element.is_insignificant())
 			.map(|(element, cnt)| {
 				let percentage = element.molar_mass * (*cnt as f64) / molar_mass;
 
@@ -239,12 +394,33 @@ impl Fertilizer for Compound {
 			.sorted()
 			.collect::<Vec<_>>()
 	}
+}
+
+impl Fertilizer for Compound {
+	/// Returns elements percentage for all elements except unimportant
+	fn components_percentage(&self, known_elts: &KnownElements) -> Vec<ElementsConcentrationsWithAliases> {
+		self.components_percentage_filtered(known_elts, false)
+	}
+
+	fn components_percentage_all(&self, known_elts: &KnownElements) -> Vec<ElementsConcentrationsWithAliases> {
+		self.components_percentage_filtered(known_elts, true)
+	}
 
 	fn name(&self) -> &str {
 		self.name.as_str()
 	}
-	fn description(&self) -> String {
-		format!("Compound: {}", self.name)
+
+	fn as_compound(&self) -> Option<&Compound> {
+		Some(self)
+	}
+	fn description(&self) -> Option<String> {
+		Some(format!("Compound: {}", self.name))
+	}
+	fn kind(&self) -> FertilizerKind {
+		FertilizerKind::Compound
+	}
+	fn validate(&self, _known_elts: &KnownElements) -> Result<()> {
+		self.molar_mass_validated().map(|_| ())
 	}
 }
 
@@ -266,6 +442,64 @@ mod tests {
 		assert_delta_eq!(k.as_ref().unwrap().molar_mass(), 39.098, MOLAR_MASS_EPSILON);
 	}
 
+	#[test]
+	fn parse_multi_digit() {
+		let known_elements = load_known_elements();
+		// Sucrose, 342.297 with IUPAC masses, slightly less with the masses from elements.toml
+		let sucrose = Compound::new("C12H22O11", &known_elements).unwrap();
+		assert_eq!(sucrose.elements[known_elements.elements.get("C").unwrap()], 12);
+		assert_eq!(sucrose.elements[known_elements.elements.get("H").unwrap()], 22);
+		assert_eq!(sucrose.elements[known_elements.elements.get("O").unwrap()], 11);
+		assert_delta_eq!(sucrose.molar_mass(), 342.293, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn parse_from_str() {
+		let kno3: Compound = "KNO3".parse().unwrap();
+		assert_delta_eq!(kno3.molar_mass(), 101.1032, MOLAR_MASS_EPSILON);
+		assert!("Ololo".parse::<Compound>().is_err());
+	}
+
+	#[test]
+	fn components_all() {
+		let known_elements = load_known_elements();
+		let compound = Compound::new("KNO3", &known_elements).unwrap();
+		assert!(compound
+			.components_percentage(&known_elements)
+			.iter()
+			.all(|elt| elt.element.name.as_ref() != "O"));
+		let all_components = compound.components_percentage_all(&known_elements);
+		let oxygen = all_components.iter().find(|elt| elt.element.name.as_ref() == "O").unwrap();
+		assert_delta_eq!(oxygen.concentration, 0.4747, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(all_components.iter().map(|elt| elt.concentration).sum::<f64>(), 1.0, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn compare_compounds() {
+		let known_elements = load_known_elements();
+		let kno3 = Compound::new("KNO3", &known_elements).unwrap();
+		let no3k = Compound::new("NO3K", &known_elements).unwrap();
+		assert_eq!(kno3, no3k);
+		assert_ne!(kno3, Compound::new("KNO2", &known_elements).unwrap());
+		let compounds = [kno3, no3k].into_iter().collect::<std::collections::HashSet<_>>();
+		assert_eq!(compounds.len(), 1);
+	}
+
+	#[test]
+	fn parse_atoms_count() {
+		let known_elements = load_known_elements();
+		// Suspicious but still allowed
+		let compound = Compound::new("K500", &known_elements).unwrap();
+		assert_eq!(compound.elements.values().copied().collect::<Vec<_>>(), vec![500]);
+		assert!(Compound::new("K1001", &known_elements).is_err());
+		assert!(Compound::new("(K100)20", &known_elements).is_err());
+		// Counts that overflow u32 must be rejected rather than wrapped
+		assert!(Compound::new("K99999999999", &known_elements).is_err());
+		assert!(Compound::new("(K999999)999999", &known_elements).is_err());
+		assert!(Compound::new("((K999)999)999", &known_elements).is_err());
+		assert!(Compound::new("K0", &known_elements).is_err());
+	}
+
 	#[test]
 	fn parse_invalid() {
 		let known_elements = load_known_elements();
@@ -292,6 +526,44 @@ mod tests {
 		assert_delta_eq!(braces.as_ref().unwrap().molar_mass(), 40.078 * 2.0, MOLAR_MASS_EPSILON);
 	}
 
+	#[test]
+	fn parse_outer_braces() {
+		let known_elements = load_known_elements();
+		let kno3 = Compound::new("(KNO3)", &known_elements);
+		assert_delta_eq!(kno3.as_ref().unwrap().molar_mass(), 101.1032, MOLAR_MASS_EPSILON);
+		let kno3 = Compound::new("(KNO3)1", &known_elements);
+		assert_delta_eq!(kno3.as_ref().unwrap().molar_mass(), 101.1032, MOLAR_MASS_EPSILON);
+		let cano3 = Compound::new("((Ca(NO3)2))", &known_elements);
+		assert_delta_eq!(cano3.as_ref().unwrap().molar_mass(), 164.086, MOLAR_MASS_EPSILON);
+		let nh4so4 = Compound::new("(NH4)2SO4", &known_elements);
+		assert_delta_eq!(nh4so4.as_ref().unwrap().molar_mass(), 132.137, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn parse_equivalent() {
+		let known_elements = load_known_elements();
+		let equivalent = [
+			("Ca(NO3)2", "(Ca)(NO3)2"),
+			("Ca(NO3)2", "((Ca(NO3)2))"),
+			("Ca(NO3)2*4H2O", "CaN2O10H8"),
+			("KNO3", "K(NO3)"),
+			("KNO3", "NO3K"),
+			("KH2PO4", "K(H2PO4)"),
+			("K2SO4", "K2(SO4)"),
+			("(NH4)2SO4", "N2H8SO4"),
+			("NH4NO3", "N2H4O3"),
+			("CH4N2O", "CO(NH2)2"),
+			("Fe(NO3)3", "FeN3O9"),
+			("MgSO4*7H2O", "MgSO4(H2O)7"),
+		];
+
+		for (canonical, other) in equivalent {
+			let canonical_mass = Compound::new(canonical, &known_elements).unwrap().molar_mass();
+			let other_mass = Compound::new(other, &known_elements).unwrap().molar_mass();
+			assert_delta_eq!(canonical_mass, other_mass, MOLAR_MASS_EPSILON);
+		}
+	}
+
 	#[test]
 	fn parse_hydrate() {
 		let known_elements = load_known_elements();
@@ -300,4 +572,83 @@ mod tests {
 		let cacl2_h2o = Compound::new("CaCl2*H2O", &known_elements);
 		assert_delta_eq!(cacl2_h2o.as_ref().unwrap().molar_mass(), 128.9993, MOLAR_MASS_EPSILON);
 	}
+
+	#[test]
+	fn serialize() {
+		let known_elements = load_known_elements();
+		let compound = Compound::new("Ca(NO3)2", &known_elements).unwrap();
+		assert_eq!(compound.to_json().unwrap(), r#"{"name":"Ca(NO3)2","elements":{"Ca":1,"N":2,"O":6}}"#);
+		let toml: toml::Value = toml::from_str(compound.to_toml().unwrap().as_str()).unwrap();
+		assert_eq!(toml["name"].as_str(), Some("Ca(NO3)2"));
+		assert_eq!(toml["elements"]["O"].as_integer(), Some(6));
+	}
+
+	#[test]
+	fn parse_single_element() {
+		let known_elements = load_known_elements();
+		for (formula, element, count, molar_mass) in [
+			("O2", "O", 2, 31.998),
+			("H2", "H", 2, 2.0156),
+			("N2", "N", 2, 28.014),
+			("P4", "P", 4, 123.896),
+			("S8", "S", 8, 256.52),
+		] {
+			let compound = Compound::new(formula, &known_elements).unwrap();
+			assert_eq!(compound.canonical_composition(), vec![(element, count)], "{}", formula);
+			assert_delta_eq!(compound.molar_mass(), molar_mass, MOLAR_MASS_EPSILON);
+		}
+	}
+
+	#[test]
+	fn parse_square_brackets() {
+		let known_elements = load_known_elements();
+		let compound = Compound::new("K4[Fe(CN)6]", &known_elements).unwrap();
+		assert_delta_eq!(compound.molar_mass(), 368.35, 0.01);
+		assert_eq!(compound, Compound::new("K4(Fe(CN)6)", &known_elements).unwrap());
+		assert_eq!(compound, Compound::new("K4Fe(CN)6", &known_elements).unwrap());
+	}
+
+	#[test]
+	fn parse_mismatched_brackets() {
+		let known_elements = load_known_elements();
+		let err = Compound::new("K4(Fe(CN)6]", &known_elements).unwrap_err();
+		assert_eq!(err.to_string(), "Mismatched brackets in K4(Fe(CN)6]: '(' is closed by ']'");
+		assert!(Compound::new("Ca[NO3)2", &known_elements).is_err());
+		assert!(Compound::new("K4[Fe(CN]6)", &known_elements).is_err());
+		assert!(Compound::new("Ca(NO3", &known_elements).is_err());
+		assert!(Compound::new("CaNO3)2", &known_elements).is_err());
+	}
+
+	#[test]
+	fn parse_hydrate_separators() {
+		let known_elements = load_known_elements();
+
+		for formula in ["MgSO4*7H2O", "MgSO4\u{00B7}7H2O", "MgSO4\u{2219}7H2O"] {
+			let compound = Compound::new(formula, &known_elements).unwrap();
+			assert_delta_eq!(compound.molar_mass(), 246.468, MOLAR_MASS_EPSILON);
+		}
+	}
+
+	#[test]
+	fn empty_name() {
+		let known_elements = load_known_elements();
+		for formula in ["", "  ", "\t"] {
+			let err = Compound::new(formula, &known_elements).unwrap_err();
+			assert_eq!(err.to_string(), "Empty compound name");
+		}
+		let compound = Compound::new(" KNO3 ", &known_elements).unwrap();
+		assert_eq!(compound.name, "KNO3");
+	}
+
+	#[test]
+	fn zero_molar_mass() {
+		let known_elements = KnownElements::new_with_string("[N]\nmolar_mass = 0.0\n").unwrap();
+		let compound = Compound::new("N2", &known_elements).unwrap();
+		assert!(compound.molar_mass_validated().is_err());
+		assert!(compound.components_percentage(&known_elements).is_empty());
+		assert!(compound.validate(&known_elements).is_err());
+		let known_elements = load_known_elements();
+		let compound = Compound::new("N2", &known_elements).unwrap();
+		assert_delta_eq!(compound.molar_mass_validated().unwrap(), 28.014, MOLAR_MASS_EPSILON);
+	}
 }
@@ -5,25 +5,33 @@ use crate::{
 	traits::{DiluteMethod, Editor},
 	Fertilizer,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use crossterm::style::Stylize;
-use itertools::Itertools;
 use rustyline::Helper;
 use serde::{Deserialize, Serialize};
 use std::{
 	cmp::Ordering,
-	fmt::{Debug, Formatter},
+	fmt::{Debug, Display, Formatter},
+	ops::{Add, Sub},
 };
 use strum::EnumString;
 
-/// How do we calculate dilution
-#[derive(Deserialize, Serialize, Clone, Copy, Debug, EnumString, Default)]
+/// How do we calculate dilution, new calculation types might be added in future
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, EnumString, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DiluteCalcType {
 	#[default]
 	ResultOfDose,
 	TargetDose,
+	/// Per-dose amount to reach the target in a 3 times per week EI schedule
+	WeeklyEI,
 }
 
+/// Fraction of water replaced by the weekly water change in EI schedule
+const EI_WATER_CHANGE_FRACTION: f64 = 0.5;
+/// Number of doses per week in EI schedule
+const EI_DOSES_PER_WEEK: f64 = 3.0;
+
 /// Element name and it's concentration
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ElementConcentrationAlias {
@@ -40,7 +48,7 @@ pub struct ElementsConcentrationsWithAliases {
 
 impl Debug for ElementsConcentrationsWithAliases {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		write!(f, "Element: {} = {:.2}%", self.element.name.clone().bold(), self.concentration * 100.0)?;
+		write!(f, "Element: {} = {:.2}%", self.element.name.as_ref().bold(), self.concentration * 100.0)?;
 
 		for alias in self.aliases.iter() {
 			write!(f, " as {}: {:.2}%", alias.element_alias.clone().bold(), alias.concentration * 100.0)?;
@@ -64,15 +72,40 @@ pub struct ElementsDosesWithAliases {
 	pub aliases: Vec<ElementAliasDose>,
 }
 
+/// Doses (in mg/L) up to this value are displayed in ug/L
+const UG_DISPLAY_THRESHOLD: f64 = 0.01;
+/// Doses and volumes below this value are treated as cancelled out when results are subtracted
+const CANCELLED_DOSE_EPSILON: f64 = 1e-9;
+
+// Returns the dose in mg/L converted to its natural unit
+fn natural_dose_units(dose: f64) -> (f64, &'static str) {
+	if dose <= UG_DISPLAY_THRESHOLD {
+		(dose * 1000.0, "ug")
+	} else {
+		(dose, "mg")
+	}
+}
+
+impl ElementsDosesWithAliases {
+	/// Returns the dose in ug/L
+	pub fn dose_ug_per_l(&self) -> f64 {
+		self.dose * 1000.0
+	}
+
+	/// Returns the dose and its natural unit (`mg` or `ug` per liter) for display
+	pub fn display_dose(&self) -> (f64, &'static str) {
+		natural_dose_units(self.dose)
+	}
+}
+
 // Helpers to output and sort structures
 impl Debug for ElementsDosesWithAliases {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		let adjust_units = |dose: f64| if dose <= 0.01 { (dose * 1000.0, "ug") } else { (dose, "mg") };
-		let (dose, units) = adjust_units(self.dose);
-		write!(f, "Element: {} = {:.3} {}/l", self.element.name.clone().bold(), dose, units)?;
+		let (dose, units) = self.display_dose();
+		write!(f, "Element: {} = {:.3} {}/l", self.element.name.as_ref().bold(), dose, units)?;
 
 		for alias in self.aliases.iter() {
-			let (dose, units) = adjust_units(alias.dose);
+			let (dose, units) = natural_dose_units(alias.dose);
 			write!(f, " as {}: {:.3} {}/l", alias.element_alias.clone().bold(), dose, units)?;
 		}
 
@@ -120,29 +153,230 @@ impl PartialEq for ElementsDosesWithAliases {
 
 impl Eq for ElementsDosesWithAliases {}
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[must_use]
 pub struct DiluteResult {
+	/// Name of the diluted fertilizer
+	#[serde(default)]
+	pub fertilizer_name: String,
+	/// Effective volume of the tank in litres
+	#[serde(default)]
+	pub tank_volume_l: f64,
 	pub compound_dose: f64,
 	pub elements_dose: Vec<ElementsDosesWithAliases>,
+	/// Volume of the solution poured per dose in mL, only for solution dosing
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub portion_volume_ml: Option<f64>,
+	/// Volume of the stock solution container in mL, only for a single fertilizer solution dosing
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub stock_volume_ml: Option<f64>,
 }
 
-fn get_element_dose_target<T: Helper>(known_elements: &KnownElements, editor: &mut Editor<T>) -> Result<(String, f64)> {
-	let input: String = editor.readline("Input target element or compound (e.g. NO3 or N): ")?;
-	let compound = Compound::new(input.as_str(), known_elements)?;
+// Sums optional values, the result is missing only if both values are missing
+fn add_optional(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+	a.into_iter().chain(b).reduce(|a, b| a + b)
+}
+
+impl DiluteResult {
+	/// Returns a copy of the result with the most concentrated elements first
+	pub fn sort_by_concentration_desc(&self) -> DiluteResult {
+		let mut result = self.clone();
+		result.elements_dose.sort_by(|elt1, elt2| elt2.dose.total_cmp(&elt1.dose));
+		result
+	}
+
+	/// Returns dose of the specific element (e.g. `N`) if it is present in the result
+	pub fn element_dose_by_name(&self, symbol: &str) -> Option<f64> {
+		self.elements_dose
+			.iter()
+			.find(|elt| elt.element.name.as_ref() == symbol)
+			.map(|elt| elt.dose)
+	}
+
+	/// Returns dose of the specific element alias (e.g. `NO3`) if it is present in the result
+	pub fn alias_dose_by_name(&self, alias: &str) -> Option<f64> {
+		self.elements_dose
+			.iter()
+			.flat_map(|elt| elt.aliases.iter())
+			.find(|elt_alias| elt_alias.element_alias == alias)
+			.map(|elt_alias| elt_alias.dose)
+	}
+}
+
+// Plain text summary without any terminal styling
+impl Display for DiluteResult {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Dose: {:.3}g", self.compound_dose)?;
+
+		for elt in self.elements_dose.iter() {
+			write!(f, "\n{}: {:.3} mg/L", elt.element.name, elt.dose)?;
+		}
+
+		Ok(())
+	}
+}
+
+// Combines doses of several fertilizers added to the same tank
+impl Add for DiluteResult {
+	type Output = DiluteResult;
+
+	fn add(self, other: DiluteResult) -> DiluteResult {
+		let mut elements_dose = self.elements_dose;
+
+		for other_elt in other.elements_dose {
+			match elements_dose.iter_mut().find(|elt| elt.element == other_elt.element) {
+				Some(elt) => {
+					elt.dose += other_elt.dose;
+
+					for other_alias in other_elt.aliases {
+						let maybe_alias = elt
+							.aliases
+							.iter_mut()
+							.find(|alias| alias.element_alias == other_alias.element_alias);
+
+						match maybe_alias {
+							Some(alias) => alias.dose += other_alias.dose,
+							None => elt.aliases.push(other_alias),
+						}
+					}
+				},
+				None => elements_dose.push(other_elt),
+			}
+		}
+
+		elements_dose.sort();
+		let fertilizer_name = match (self.fertilizer_name.is_empty(), other.fertilizer_name.is_empty()) {
+			(true, _) => other.fertilizer_name,
+			(false, true) => self.fertilizer_name,
+			(false, false) => format!("{} + {}", self.fertilizer_name, other.fertilizer_name),
+		};
+		DiluteResult {
+			fertilizer_name,
+			tank_volume_l: self.tank_volume_l.max(other.tank_volume_l),
+			compound_dose: self.compound_dose + other.compound_dose,
+			elements_dose,
+			portion_volume_ml: add_optional(self.portion_volume_ml, other.portion_volume_ml),
+			// Stock solutions of different fertilizers are separate containers, so only a single one is kept
+			stock_volume_ml: match (self.stock_volume_ml, other.stock_volume_ml) {
+				(Some(volume), None) | (None, Some(volume)) => Some(volume),
+				_ => None,
+			},
+		}
+	}
+}
+
+// Difference between two results, e.g. concentration decrease after a water change; doses might be negative.
+// The difference describes the left result: its name (without the subtracted fertilizer), tank and stock
+// solution are kept, and the elements that are cancelled out completely are omitted
+impl Sub for DiluteResult {
+	type Output = DiluteResult;
+
+	fn sub(self, other: DiluteResult) -> DiluteResult {
+		let mut elements_dose = self.elements_dose;
+
+		for other_elt in other.elements_dose {
+			match elements_dose.iter_mut().find(|elt| elt.element == other_elt.element) {
+				Some(elt) => {
+					elt.dose -= other_elt.dose;
+
+					for other_alias in other_elt.aliases {
+						let maybe_alias = elt
+							.aliases
+							.iter_mut()
+							.find(|alias| alias.element_alias == other_alias.element_alias);
+
+						match maybe_alias {
+							Some(alias) => alias.dose -= other_alias.dose,
+							None => elt.aliases.push(ElementAliasDose { dose: -other_alias.dose, ..other_alias }),
+						}
+					}
+				},
+				None => elements_dose.push(ElementsDosesWithAliases {
+					dose: -other_elt.dose,
+					aliases: other_elt
+						.aliases
+						.into_iter()
+						.map(|alias| ElementAliasDose { dose: -alias.dose, ..alias })
+						.collect(),
+					..other_elt
+				}),
+			}
+		}
+
+		elements_dose.retain(|elt| elt.dose.abs() > CANCELLED_DOSE_EPSILON);
+		elements_dose.sort();
+		let fertilizer_name = match self
+			.fertilizer_name
+			.strip_suffix(format!(" + {}", other.fertilizer_name).as_str())
+		{
+			Some(name) => name.to_owned(),
+			None => self.fertilizer_name,
+		};
+		let portion_volume_ml = self
+			.portion_volume_ml
+			.map(|volume| volume - other.portion_volume_ml.unwrap_or_default())
+			.filter(|volume| volume.abs() > CANCELLED_DOSE_EPSILON);
+		DiluteResult {
+			fertilizer_name,
+			tank_volume_l: self.tank_volume_l,
+			compound_dose: self.compound_dose - other.compound_dose,
+			elements_dose,
+			portion_volume_ml,
+			// A stock solution of the subtracted fertilizer is not a part of the difference
+			stock_volume_ml: if other.stock_volume_ml.is_some() { None } else { self.stock_volume_ml },
+		}
+	}
+}
+
+// Dosing inputs must be positive finite numbers, NaN would otherwise spread into all results
+fn check_positive(what: &str, value: f64) -> Result<()> {
+	if value.is_finite() && value > 0.0 {
+		Ok(())
+	} else {
+		Err(anyhow!("{} must be positive: {}", what, value))
+	}
+}
+
+// Resolves the target element (or its alias or compound) to the element name and its fraction
+fn target_element_fraction(input: &str, known_elements: &KnownElements) -> Result<(String, f64)> {
+	if let Some((elt, formula)) = known_elements.alias_to_element(input.trim()) {
+		return Ok((elt.name.to_string(), elt.element_from_alias_rate(formula, known_elements)?))
+	}
+
+	let compound = Compound::new(input, known_elements).inspect_err(|_| {
+		let available = known_elements
+			.elements_by_priority()
+			.iter()
+			.map(|elt| elt.name.as_ref())
+			.collect::<Vec<&str>>();
+		eprintln!("Available elements: {}", available.join(", "));
+	})?;
 	let concentrations = compound.components_percentage(known_elements);
-	let top_elt = concentrations[0].element.name.clone();
+	let top_elt = concentrations
+		.first()
+		.ok_or_else(|| anyhow!("target {} has no significant elements", input.trim()))?;
+	Ok((top_elt.element.name.to_string(), top_elt.concentration))
+}
+
+fn get_element_dose_target<T: Helper>(known_elements: &KnownElements, editor: &mut Editor<T>) -> Result<(String, f64)> {
+	let input: String = editor.readline("Input target element or compound (e.g. NO3, Nitrate or N): ")?;
+	let (top_elt, fraction) = target_element_fraction(input.as_str(), known_elements)?;
 	let input: String = editor.readline("Input target element concentration (mg/l): ")?;
 	let target = input.parse::<f64>()?;
-	Ok((top_elt, target * concentrations[0].concentration))
+	Ok((top_elt, target * fraction))
 }
 
 fn element_from_compound(elt_name: &str, known_elements: &KnownElements) -> Result<(Element, f64)> {
 	if let Some(elt) = known_elements.elements.get(elt_name) {
 		Ok((elt.clone(), 1.0))
 	} else {
-		let compound = Compound::new(elt_name, known_elements)?;
+		// Not an element, so it must be an alias (e.g. NO3)
+		let compound = Compound::new(elt_name, known_elements)
+			.with_context(|| format!("invalid target element '{}'", elt_name))?;
 		let concentrations = compound.components_percentage(known_elements);
-		let top_elt = &concentrations[0];
+		let top_elt = concentrations
+			.first()
+			.ok_or_else(|| anyhow!("target {} has no significant elements", elt_name))?;
 		Ok((top_elt.element.clone(), 1.0 / top_elt.concentration))
 	}
 }
@@ -164,18 +398,76 @@ fn dilute_fertilizer(
 				.collect::<Vec<_>>();
 			ElementsDosesWithAliases { element: elt_conc.element.clone(), dose: elt_conc.concentration * mult, aliases }
 		})
-		.sorted()
 		.collect::<Vec<_>>()
 }
 
 /// A concrete implementation of the dosing with the value in grams
-#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(from = "DryDosingData")]
 pub struct DryDosing {
-	pub dilute_input: f64,
+	/// Dose in grams, used by `ResultOfDose`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dose_grams: Option<f64>,
+	/// Concentration of the target element in mg/L, used by `TargetDose` and `WeeklyEI`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub target_mg_per_l: Option<f64>,
 	pub what: DiluteCalcType,
 	pub target_element: Option<String>,
 }
 
+// Serialized form of `DryDosing`, also accepts the legacy `dilute_input` field that is either a dose
+// or a target concentration depending on the calculation type
+#[derive(Deserialize)]
+struct DryDosingData {
+	#[serde(default)]
+	dose_grams: Option<f64>,
+	#[serde(default)]
+	target_mg_per_l: Option<f64>,
+	#[serde(default)]
+	dilute_input: Option<f64>,
+	what: DiluteCalcType,
+	#[serde(default)]
+	target_element: Option<String>,
+}
+
+impl From<DryDosingData> for DryDosing {
+	fn from(data: DryDosingData) -> Self {
+		let (dose_grams, target_mg_per_l) = match data.what {
+			DiluteCalcType::ResultOfDose => (data.dose_grams.or(data.dilute_input), data.target_mg_per_l),
+			DiluteCalcType::TargetDose | DiluteCalcType::WeeklyEI =>
+				(data.dose_grams, data.target_mg_per_l.or(data.dilute_input)),
+		};
+
+		DryDosing { dose_grams, target_mg_per_l, what: data.what, target_element: data.target_element }
+	}
+}
+
+impl DryDosing {
+	/// Dosing of the specific amount of fertilizer in grams
+	pub fn with_dose(dose: f64) -> Self {
+		Self { dose_grams: Some(dose), what: DiluteCalcType::ResultOfDose, ..Default::default() }
+	}
+
+	/// Dosing to reach the target concentration of the element (or its alias) in mg/L
+	pub fn with_target(element: impl Into<String>, target_mg_per_l: f64) -> Self {
+		Self {
+			target_mg_per_l: Some(target_mg_per_l),
+			what: DiluteCalcType::TargetDose,
+			target_element: Some(element.into()),
+			..Default::default()
+		}
+	}
+
+	// Returns the input value that is relevant for the calculation type
+	fn input(&self) -> Result<f64> {
+		match self.what {
+			DiluteCalcType::ResultOfDose => self.dose_grams.ok_or_else(|| anyhow!("no dose in grams defined")),
+			DiluteCalcType::TargetDose | DiluteCalcType::WeeklyEI =>
+				self.target_mg_per_l.ok_or_else(|| anyhow!("no target concentration defined")),
+		}
+	}
+}
+
 impl DiluteMethod for DryDosing {
 	fn new_from_stdin<T: Helper>(
 		what: DiluteCalcType,
@@ -186,11 +478,16 @@ impl DiluteMethod for DryDosing {
 			DiluteCalcType::ResultOfDose => {
 				let input: String = editor.readline("Dose size in grams (e.g. 2.5): ")?;
 				let dose = input.parse::<f64>()?;
-				Ok(Self { dilute_input: dose, what, ..Default::default() })
+				Ok(Self { dose_grams: Some(dose), what, ..Default::default() })
 			},
-			DiluteCalcType::TargetDose => {
-				let (target_element, dilute_input) = get_element_dose_target(known_elements, editor)?;
-				Ok(Self { dilute_input, what, target_element: Some(target_element) })
+			DiluteCalcType::TargetDose | DiluteCalcType::WeeklyEI => {
+				let (target_element, target) = get_element_dose_target(known_elements, editor)?;
+				Ok(Self {
+					target_mg_per_l: Some(target),
+					what,
+					target_element: Some(target_element),
+					..Default::default()
+				})
 			},
 		}
 	}
@@ -204,12 +501,20 @@ impl DiluteMethod for DryDosing {
 		Ok(res)
 	}
 
+	fn validate(&self) -> Result<()> {
+		check_positive("dose", self.input()?)
+	}
+
 	fn dilute(&self, fertilizer: &dyn Fertilizer, known_elements: &KnownElements, tank: &Tank) -> Result<DiluteResult> {
+		fertilizer.validate(known_elements)?;
 		let concentrations = fertilizer.components_percentage(known_elements);
+		let input = self.input()?;
+		// Input is a multiplier below, so it is checked even if the caller skipped validation
+		check_positive("dose", input)?;
 		let mult =
 			match self.what {
-				DiluteCalcType::ResultOfDose => self.dilute_input * 1000.0 / tank.effective_volume() as f64,
-				DiluteCalcType::TargetDose => {
+				DiluteCalcType::ResultOfDose => input * 1000.0 / tank.effective_volume(),
+				DiluteCalcType::TargetDose | DiluteCalcType::WeeklyEI => {
 					// Get target element concentration
 					let target_elt_name = self
 						.target_element
@@ -223,20 +528,60 @@ impl DiluteMethod for DryDosing {
 								anyhow!("target element {:?} is not in the fertilizer", target_elt_name)
 							})?)
 							.unwrap();
-					self.dilute_input / (fert_elt.concentration * elt_conc)
+					let target = match self.what {
+						DiluteCalcType::WeeklyEI => input / EI_WATER_CHANGE_FRACTION / EI_DOSES_PER_WEEK,
+						_ => input,
+					};
+					target / (fert_elt.concentration * elt_conc)
 				},
 			};
 		// For dry dosing we simply dilute all components by a tank's effective volume
 		let concentrations = dilute_fertilizer(concentrations, mult);
 		Ok(DiluteResult {
-			compound_dose: mult * tank.effective_volume() as f64 / 1000.0,
+			fertilizer_name: fertilizer.name().to_string(),
+			tank_volume_l: tank.effective_volume(),
+			compound_dose: mult * tank.effective_volume() / 1000.0,
 			elements_dose: concentrations,
+			portion_volume_ml: None,
+			stock_volume_ml: None,
 		})
 	}
 }
 
+// Tank volume is not a part of the dosing, so only the dose itself is described
+impl Display for DryDosing {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let target = self.target_element.as_deref().unwrap_or("?");
+		let input = self.input().map_or_else(|_| "?".to_owned(), |input| input.to_string());
+
+		match self.what {
+			DiluteCalcType::ResultOfDose => write!(f, "Dry dose: {} g", input),
+			DiluteCalcType::TargetDose => write!(f, "Dry dose: target {} mg/L of {}", input, target),
+			DiluteCalcType::WeeklyEI => {
+				write!(f, "Dry dose: weekly EI target {} mg/L of {}", input, target)
+			},
+		}
+	}
+}
+
+/// Dry dosing of several fertilizers to the same tank at once
+pub struct BatchDosing {
+	pub entries: Vec<(Box<dyn Fertilizer>, DryDosing)>,
+}
+
+impl BatchDosing {
+	/// Dilute all fertilizers in a specific tank and return the combined result
+	pub fn dilute_all(&self, known_elements: &KnownElements, tank: &Tank) -> Result<DiluteResult> {
+		self.entries
+			.iter()
+			.try_fold(DiluteResult::default(), |acc, (fertilizer, dosing)| {
+				Ok(acc + dosing.dilute(&**fertilizer, known_elements, tank)?)
+			})
+	}
+}
+
 /// A concrete implementation of the dosing by dissolving dry salt in a concentrated solution
-#[derive(Default, Debug, Deserialize, Serialize, Clone)]
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct SolutionDosing {
 	pub container_volume: f64,
 	pub portion_volume: f64,
@@ -245,6 +590,51 @@ pub struct SolutionDosing {
 	pub target_element: Option<String>,
 }
 
+impl Display for SolutionDosing {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self.what {
+			DiluteCalcType::ResultOfDose => write!(f, "Solution dose: {} g", self.solution_input)?,
+			_ => write!(
+				f,
+				"Solution dose: target {} mg/L of {}",
+				self.solution_input,
+				self.target_element.as_deref().unwrap_or("?")
+			)?,
+		}
+
+		write!(f, " in {} mL container, {} mL per dose", self.container_volume, self.portion_volume)
+	}
+}
+
+impl SolutionDosing {
+	/// Dosing of the solution with the specific amount of fertilizer in grams dissolved in a container,
+	/// the container should be set by `with_container`
+	pub fn with_dose(dose: f64) -> Self {
+		Self { solution_input: dose, what: DiluteCalcType::ResultOfDose, ..Default::default() }
+	}
+
+	/// Dosing of the solution to reach the target concentration of the element (or its alias) in mg/L,
+	/// the container should be set by `with_container`
+	pub fn with_target(element: impl Into<String>, target_mg_per_l: f64) -> Self {
+		Self {
+			solution_input: target_mg_per_l,
+			what: DiluteCalcType::TargetDose,
+			target_element: Some(element.into()),
+			..Default::default()
+		}
+	}
+
+	/// Sets the container volume and the volume poured per dose, both in mL
+	pub fn with_container(self, container_volume: f64, portion_volume: f64) -> Self {
+		Self { container_volume, portion_volume, ..self }
+	}
+
+	/// Returns concentration of the stock solution in g/L for the specific dose of fertilizer in grams
+	pub fn stock_solution_concentration(&self, dose: f64) -> f64 {
+		dose * 1000.0 / self.container_volume
+	}
+}
+
 impl DiluteMethod for SolutionDosing {
 	fn new_from_stdin<T: Helper>(
 		what: DiluteCalcType,
@@ -275,6 +665,7 @@ impl DiluteMethod for SolutionDosing {
 					target_element: Some(target_element),
 				})
 			},
+			DiluteCalcType::WeeklyEI => Err(anyhow!("weekly EI calculation is supported for dry dosing only")),
 		}
 	}
 
@@ -291,7 +682,23 @@ impl DiluteMethod for SolutionDosing {
 		Ok(res)
 	}
 
+	fn validate(&self) -> Result<()> {
+		check_positive("container volume", self.container_volume)?;
+		check_positive("portion volume", self.portion_volume)?;
+		if self.portion_volume > self.container_volume {
+			return Err(anyhow!(
+				"portion volume {} is larger than container volume {}",
+				self.portion_volume,
+				self.container_volume
+			))
+		}
+		check_positive("dose", self.solution_input)
+	}
+
 	fn dilute(&self, fertilizer: &dyn Fertilizer, known_elements: &KnownElements, tank: &Tank) -> Result<DiluteResult> {
+		// Container volume is a divisor below, so it is checked even if the caller skipped validation
+		check_positive("container volume", self.container_volume)?;
+		fertilizer.validate(known_elements)?;
 		let concentrations = fertilizer.components_percentage(known_elements);
 		let dose =
 			match self.what {
@@ -310,14 +717,23 @@ impl DiluteMethod for SolutionDosing {
 								anyhow!("target element {} is not in the fertilizer", target_elt_name)
 							})?)
 							.unwrap();
-					self.solution_input * tank.effective_volume() as f64 / (fert_elt.concentration * elt_conc) *
-						self.container_volume / self.portion_volume /
+					self.solution_input * tank.effective_volume() / (fert_elt.concentration * elt_conc) *
+						self.container_volume /
+						self.portion_volume /
 						1000.0
 				},
+				DiluteCalcType::WeeklyEI => bail!("weekly EI calculation is supported for dry dosing only"),
 			};
-		let mult = (dose * 1000.0 / self.container_volume * self.portion_volume) / tank.effective_volume() as f64;
+		let mult = (dose * 1000.0 / self.container_volume * self.portion_volume) / tank.effective_volume();
 		let concentrations = dilute_fertilizer(concentrations, mult);
-		Ok(DiluteResult { compound_dose: dose, elements_dose: concentrations })
+		Ok(DiluteResult {
+			fertilizer_name: fertilizer.name().to_string(),
+			tank_volume_l: tank.effective_volume(),
+			compound_dose: dose,
+			elements_dose: concentrations,
+			portion_volume_ml: Some(self.portion_volume),
+			stock_volume_ml: Some(self.container_volume),
+		})
 	}
 }
 
@@ -331,33 +747,348 @@ mod tests {
 		let tank = sample_tank();
 		let known_elts = load_known_elements();
 		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
-		let dosing =
-			Box::new(DryDosing { dilute_input: 1.0, what: DiluteCalcType::ResultOfDose, ..Default::default() });
+		let dosing = Box::new(DryDosing::with_dose(1.0));
 		let results = dosing.dilute(&*compound, &known_elts, &tank).unwrap();
+		assert_eq!(results.fertilizer_name, "KNO3");
+		assert_delta_eq!(results.tank_volume_l, tank.effective_volume(), MOLAR_MASS_EPSILON);
 		assert!(!results.elements_dose.is_empty());
-		assert_eq!(results.elements_dose[0].element.name.as_str(), "N");
+		assert_eq!(results.elements_dose[0].element.name.as_ref(), "N");
 		assert_delta_eq!(results.elements_dose[0].dose, 0.815, MOLAR_MASS_EPSILON);
-		assert_eq!(results.elements_dose[1].element.name.as_str(), "K");
+		assert_eq!(results.elements_dose[1].element.name.as_ref(), "K");
 		assert_delta_eq!(results.elements_dose[1].dose, 2.275, MOLAR_MASS_EPSILON);
 	}
 
+	#[test]
+	fn test_dilute_order() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let fertilizers = load_known_fertilizers(&known_elts);
+		let fertilizer = fertilizers.known_fertilizers.get("Solufeed High K").unwrap();
+		let dosing = DryDosing::with_dose(1.0);
+		let concentrations = fertilizer.components_percentage(&known_elts);
+		let results = dosing.dilute(&**fertilizer, &known_elts, &tank).unwrap();
+		assert_eq!(
+			results
+				.elements_dose
+				.iter()
+				.map(|elt| elt.element.name.as_ref())
+				.collect::<Vec<_>>(),
+			concentrations.iter().map(|elt| elt.element.name.as_ref()).collect::<Vec<_>>()
+		);
+		assert!(results.elements_dose.windows(2).all(|pair| pair[0] <= pair[1]));
+	}
+
+	#[test]
+	fn test_dose_by_name() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let dosing = DryDosing::with_dose(1.0);
+		let results = dosing.dilute(&*compound, &known_elts, &tank).unwrap();
+		assert_delta_eq!(results.element_dose_by_name("N").unwrap(), 0.815, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(results.alias_dose_by_name("NO3").unwrap(), 3.608, MOLAR_MASS_EPSILON);
+		assert!(results.element_dose_by_name("P").is_none());
+		assert!(results.alias_dose_by_name("PO4").is_none());
+	}
+
+	#[test]
+	fn test_sort_by_concentration() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let dosing = DryDosing::with_dose(1.0);
+		let results = dosing
+			.dilute(&*compound, &known_elts, &tank)
+			.unwrap()
+			.sort_by_concentration_desc();
+		assert_eq!(results.elements_dose[0].element.name.as_ref(), "K");
+		assert_eq!(results.elements_dose[1].element.name.as_ref(), "N");
+	}
+
+	#[test]
+	fn test_display_result() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let dosing = DryDosing::with_dose(1.0);
+		let results = dosing.dilute(&*compound, &known_elts, &tank).unwrap();
+		assert_eq!(results.to_string(), "Dose: 1.000g\nN: 0.815 mg/L\nK: 2.275 mg/L");
+	}
+
+	#[test]
+	fn test_display_dosing() {
+		let dosing = DryDosing::with_dose(2.5);
+		assert_eq!(dosing.to_string(), "Dry dose: 2.5 g");
+		let dosing = DryDosing::with_target("N", 15.0);
+		assert_eq!(dosing.to_string(), "Dry dose: target 15 mg/L of N");
+		let dosing = SolutionDosing::with_dose(10.0).with_container(1000.0, 100.0);
+		assert_eq!(dosing.to_string(), "Solution dose: 10 g in 1000 mL container, 100 mL per dose");
+	}
+
+	#[test]
+	fn test_kno3_weekly_ei() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let target = DryDosing::with_target("NO3", 15.0);
+		let weekly = DryDosing { what: DiluteCalcType::WeeklyEI, ..target.clone() };
+		let target_results = target.dilute(&*compound, &known_elts, &tank).unwrap();
+		let weekly_results = weekly.dilute(&*compound, &known_elts, &tank).unwrap();
+		assert_delta_eq!(weekly_results.compound_dose, target_results.compound_dose / 1.5, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_water_change() {
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let dosing = DryDosing::with_target("N", 10.0);
+		let changed_tank = sample_tank().with_water_change(30.0).unwrap();
+		let small_tank = Tank::new_from_toml("volume = 60").unwrap();
+		let changed_results = dosing.dilute(&*compound, &known_elts, &changed_tank).unwrap();
+		let small_results = dosing.dilute(&*compound, &known_elts, &small_tank).unwrap();
+		assert_delta_eq!(changed_results.compound_dose, small_results.compound_dose, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_water_change_delta() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
+		let dosing = DryDosing::with_dose(1.0);
+		let current = dosing.dilute(&*compound, &known_elts, &tank).unwrap();
+		// After 50% water change only a half of the fertilizer remains in the tank
+		let after_water_change = DryDosing { dose_grams: Some(0.5), ..dosing }
+			.dilute(&*compound, &known_elts, &tank)
+			.unwrap();
+		let delta = current.clone() - after_water_change.clone();
+		assert_eq!(delta.fertilizer_name, "KNO3");
+		assert_delta_eq!(delta.compound_dose, 0.5, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(delta.element_dose_by_name("N").unwrap(), 0.4075, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(delta.alias_dose_by_name("NO3").unwrap(), 1.804, MOLAR_MASS_EPSILON);
+		let reverse = after_water_change - current;
+		assert_delta_eq!(reverse.element_dose_by_name("K").unwrap(), -1.1375, MOLAR_MASS_EPSILON);
+	}
+
+	// Compares all fields of two results
+	fn assert_results_eq(result: &DiluteResult, expected: &DiluteResult) {
+		assert_eq!(result.fertilizer_name, expected.fertilizer_name);
+		assert_delta_eq!(result.tank_volume_l, expected.tank_volume_l, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(result.compound_dose, expected.compound_dose, MOLAR_MASS_EPSILON);
+		assert_eq!(result.portion_volume_ml, expected.portion_volume_ml);
+		assert_eq!(result.stock_volume_ml, expected.stock_volume_ml);
+		assert_eq!(result.elements_dose.len(), expected.elements_dose.len());
+		for (elt, expected_elt) in result.elements_dose.iter().zip(expected.elements_dose.iter()) {
+			assert_eq!(elt.element, expected_elt.element);
+			assert_delta_eq!(elt.dose, expected_elt.dose, MOLAR_MASS_EPSILON);
+			assert_eq!(elt.aliases.len(), expected_elt.aliases.len());
+			for (alias, expected_alias) in elt.aliases.iter().zip(expected_elt.aliases.iter()) {
+				assert_eq!(alias.element_alias, expected_alias.element_alias);
+				assert_delta_eq!(alias.dose, expected_alias.dose, MOLAR_MASS_EPSILON);
+			}
+		}
+	}
+
+	#[test]
+	fn test_add_sub_inverse() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let kno3 = Compound::new("KNO3", &known_elts).unwrap();
+		let mgso4 = Compound::new("MgSO4", &known_elts).unwrap();
+		let dry = DryDosing::with_dose(1.0).dilute(&kno3, &known_elts, &tank).unwrap();
+		let solution = SolutionDosing::with_dose(10.0)
+			.with_container(1000.0, 50.0)
+			.dilute(&mgso4, &known_elts, &tank)
+			.unwrap();
+
+		for (a, b) in [(&dry, &solution), (&solution, &dry), (&dry, &dry)] {
+			let sum = a.clone() + b.clone();
+			assert_results_eq(&(sum - b.clone()), a);
+		}
+	}
+
 	#[test]
 	fn test_kno3_solution() {
 		let tank = sample_tank();
 		let known_elts = load_known_elements();
 		let compound: Box<dyn Fertilizer> = Box::new(Compound::new("KNO3", &known_elts).unwrap());
-		let dosing = Box::new(SolutionDosing {
-			solution_input: 10.0,
-			container_volume: 1000.0,
-			portion_volume: 100.0,
-			what: DiluteCalcType::ResultOfDose,
-			..Default::default()
-		});
+		let dosing = Box::new(SolutionDosing::with_dose(10.0).with_container(1000.0, 100.0));
 		let results = dosing.dilute(&*compound, &known_elts, &tank).unwrap();
 		assert!(!results.elements_dose.is_empty());
-		assert_eq!(results.elements_dose[0].element.name.as_str(), "N");
+		assert_eq!(results.elements_dose[0].element.name.as_ref(), "N");
 		assert_delta_eq!(results.elements_dose[0].dose, 0.815, MOLAR_MASS_EPSILON);
-		assert_eq!(results.elements_dose[1].element.name.as_str(), "K");
+		assert_eq!(results.elements_dose[1].element.name.as_ref(), "K");
 		assert_delta_eq!(results.elements_dose[1].dose, 2.275, MOLAR_MASS_EPSILON);
+		assert_eq!(results.portion_volume_ml, Some(100.0));
+		assert_eq!(results.stock_volume_ml, Some(1000.0));
+		let dry_results = DryDosing::with_dose(1.0).dilute(&*compound, &known_elts, &tank).unwrap();
+		assert!(dry_results.portion_volume_ml.is_none());
+		assert!(dry_results.stock_volume_ml.is_none());
+		assert!((results.clone() + results).stock_volume_ml.is_none());
+	}
+
+	#[test]
+	fn test_display_dose() {
+		let known_elts = load_known_elements();
+		let iron = known_elts.elements.get("Fe").unwrap().clone();
+		let dose = |dose: f64| ElementsDosesWithAliases { element: iron.clone(), dose, aliases: vec![] };
+		let (value, units) = dose(0.01).display_dose();
+		assert_delta_eq!(value, 10.0, MOLAR_MASS_EPSILON);
+		assert_eq!(units, "ug");
+		let (value, units) = dose(0.0101).display_dose();
+		assert_delta_eq!(value, 0.0101, MOLAR_MASS_EPSILON);
+		assert_eq!(units, "mg");
+		assert_delta_eq!(dose(0.0101).dose_ug_per_l(), 10.1, MOLAR_MASS_EPSILON);
+		assert!(format!("{:?}", dose(0.005)).contains("5.000 ug/l"));
+	}
+
+	#[test]
+	fn test_stock_solution_concentration() {
+		let dosing = SolutionDosing { container_volume: 500.0, portion_volume: 10.0, ..Default::default() };
+		assert_delta_eq!(dosing.stock_solution_concentration(10.0), 20.0, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_batch_dry() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let dosing = DryDosing::with_dose(1.0);
+		let batch = BatchDosing {
+			entries: vec![
+				(Box::new(Compound::new("KNO3", &known_elts).unwrap()), dosing.clone()),
+				(Box::new(Compound::new("KH2PO4", &known_elts).unwrap()), dosing),
+			],
+		};
+		let results = batch.dilute_all(&known_elts, &tank).unwrap();
+		assert_eq!(results.fertilizer_name, "KNO3 + KH2PO4");
+		assert_delta_eq!(results.compound_dose, 2.0, MOLAR_MASS_EPSILON);
+		assert_eq!(results.elements_dose.len(), 3);
+		assert_eq!(results.elements_dose[0].element.name.as_ref(), "N");
+		assert_delta_eq!(results.elements_dose[0].dose, 0.815, MOLAR_MASS_EPSILON);
+		assert_eq!(results.elements_dose[1].element.name.as_ref(), "P");
+		assert_delta_eq!(results.elements_dose[1].dose, 1.339, MOLAR_MASS_EPSILON);
+		assert_eq!(results.elements_dose[2].element.name.as_ref(), "K");
+		// 2.275 from KNO3 and 1.690 from KH2PO4
+		assert_delta_eq!(results.elements_dose[2].dose, 3.965, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_calc_type_roundtrip() {
+		for what in [DiluteCalcType::ResultOfDose, DiluteCalcType::TargetDose, DiluteCalcType::WeeklyEI] {
+			let json = serde_json::to_string(&what).unwrap();
+			assert_eq!(serde_json::from_str::<DiluteCalcType>(json.as_str()).unwrap(), what);
+		}
+		let dosing = DryDosing::with_target("N", 15.0);
+		let json = serde_json::to_string(&dosing).unwrap();
+		assert!(json.contains("\"what\":\"TargetDose\""), "{}", json);
+		assert_eq!(serde_json::from_str::<DryDosing>(json.as_str()).unwrap(), dosing);
+		let dosing = SolutionDosing::with_target("NO3", 10.0).with_container(500.0, 10.0);
+		let json = serde_json::to_string(&dosing).unwrap();
+		assert_eq!(serde_json::from_str::<SolutionDosing>(json.as_str()).unwrap(), dosing);
+	}
+
+	#[test]
+	fn test_dry_dosing_legacy_input() {
+		let dosing: DryDosing =
+			serde_json::from_str(r#"{"dilute_input": 15.0, "what": "TargetDose", "target_element": "N"}"#).unwrap();
+		assert_eq!(dosing.target_mg_per_l, Some(15.0));
+		assert!(dosing.dose_grams.is_none());
+		let dosing: DryDosing = serde_json::from_str(r#"{"dilute_input": 2.5, "what": "ResultOfDose"}"#).unwrap();
+		assert_eq!(dosing.dose_grams, Some(2.5));
+		assert!(dosing.target_mg_per_l.is_none());
+		let json = serde_json::to_string(&dosing).unwrap();
+		assert!(!json.contains("dilute_input"), "{}", json);
+		assert!(json.contains("\"dose_grams\":2.5"), "{}", json);
+		let dosing: DryDosing = toml::from_str("dose_grams = 1.0\nwhat = \"ResultOfDose\"\n").unwrap();
+		assert_eq!(dosing.dose_grams, Some(1.0));
+		assert!(DryDosing { what: DiluteCalcType::TargetDose, ..dosing }.validate().is_err());
+	}
+
+	#[test]
+	fn test_validate_dosing() {
+		let dosing = DryDosing::with_dose(1.0);
+		assert!(dosing.validate().is_ok());
+		let dosing = DryDosing::with_dose(0.0);
+		assert!(dosing.validate().is_err());
+
+		let dosing = SolutionDosing::with_dose(10.0).with_container(1000.0, 100.0);
+		assert!(dosing.validate().is_ok());
+		assert!(SolutionDosing { container_volume: 0.0, ..dosing.clone() }.validate().is_err());
+		assert!(SolutionDosing { portion_volume: 0.0, ..dosing.clone() }.validate().is_err());
+		assert!(SolutionDosing { portion_volume: 2000.0, ..dosing.clone() }.validate().is_err());
+		assert!(SolutionDosing { solution_input: -1.0, ..dosing.clone() }.validate().is_err());
+
+		// NaN must not pass the positivity checks
+		let known_elts = load_known_elements();
+		let compound = Compound::new("KNO3", &known_elts).unwrap();
+		assert!(DryDosing::with_dose(f64::NAN).validate().is_err());
+		assert!(DryDosing::with_target("N", f64::NAN).validate().is_err());
+		assert!(DryDosing::with_dose(f64::INFINITY).validate().is_err());
+		assert!(DryDosing::with_dose(f64::NAN)
+			.dilute(&compound, &known_elts, &sample_tank())
+			.is_err());
+		assert!(SolutionDosing { container_volume: f64::NAN, ..dosing.clone() }
+			.validate()
+			.is_err());
+		assert!(SolutionDosing { portion_volume: f64::NAN, ..dosing.clone() }
+			.validate()
+			.is_err());
+		assert!(SolutionDosing { solution_input: f64::NAN, ..dosing.clone() }
+			.validate()
+			.is_err());
+		assert!(SolutionDosing { container_volume: f64::NAN, ..dosing }
+			.dilute(&compound, &known_elts, &sample_tank())
+			.is_err());
+	}
+
+	#[test]
+	fn test_unknown_target_element() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound = Compound::new("KNO3", &known_elts).unwrap();
+		let err = DryDosing::with_target("Xy", 10.0)
+			.dilute(&compound, &known_elts, &tank)
+			.err()
+			.unwrap();
+		assert_eq!(format!("{:#}", err), "invalid target element 'Xy': Unknown element: Xy");
+		// Parser errors are not hidden behind a generic message
+		let err = DryDosing::with_target("Ca(NO3]2", 10.0)
+			.dilute(&compound, &known_elts, &tank)
+			.err()
+			.unwrap();
+		assert!(format!("{:#}", err).contains("Mismatched brackets"), "{:#}", err);
+		let err = DryDosing::with_target("P", 10.0)
+			.dilute(&compound, &known_elts, &tank)
+			.err()
+			.unwrap();
+		assert!(err.to_string().contains("is not in the fertilizer"), "{}", err);
+		assert!(DryDosing::with_target("H2O", 10.0)
+			.dilute(&compound, &known_elts, &tank)
+			.is_err());
+	}
+
+	#[test]
+	fn test_target_element_fraction() {
+		let known_elts = load_known_elements();
+		let (element, fraction) = target_element_fraction("NO3", &known_elts).unwrap();
+		assert_eq!(element, "N");
+		assert_delta_eq!(fraction, 0.2259, MOLAR_MASS_EPSILON);
+		// Water has only insignificant elements, so there is no target
+		let err = target_element_fraction("H2O", &known_elts).unwrap_err();
+		assert_eq!(err.to_string(), "target H2O has no significant elements");
+		let known_elts = KnownElements::new_with_string("[N]\nmolar_mass = 0.0\n").unwrap();
+		assert!(target_element_fraction("N2", &known_elts).is_err());
+	}
+
+	#[test]
+	fn test_solution_empty_container() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound = Compound::new("KNO3", &known_elts).unwrap();
+		for dosing in
+			[SolutionDosing::with_dose(10.0), SolutionDosing::with_target("N", 10.0).with_container(0.0, 10.0)]
+		{
+			let err = dosing.dilute(&compound, &known_elts, &tank).err().unwrap();
+			assert!(err.to_string().contains("container volume must be positive"), "{}", err);
+		}
 	}
 }
@@ -1,24 +1,49 @@
+//! Chemical elements known to the calculator.
+//!
+//! `Element::priority` defines the order of elements in all components and doses outputs.
+//! Higher priority elements appear first; 0 = unset. Recommended: macronutrients 100–90,
+//! micronutrients 80–70, rare elements 60–50. Elements with the same priority are sorted by name.
+//! The bundled `elements.toml` keeps its historical, lower values, which follow the same order.
+
 use anyhow::{anyhow, Result};
-use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
+use serde::{
+	de::{Error as DeError, MapAccess, Visitor},
+	Deserialize, Deserializer, Serialize,
+};
 use std::{
 	cmp::Ordering,
 	collections::HashMap,
-	fmt::Debug,
+	fmt::{self, Debug, Formatter},
 	fs,
 	hash::{Hash, Hasher},
 	path::Path,
+	sync::Arc,
 };
 
 use crate::compound::Compound;
+use itertools::Itertools;
+
+/// Embedded elements database
+pub const EMBEDDED_ELEMENTS: &str = include_str!("../elements.toml");
+
+/// Elements from the embedded database, used when no specific database is available
+pub static GLOBAL_KNOWN_ELEMENTS: Lazy<KnownElements> =
+	Lazy::new(|| KnownElements::new_with_string(EMBEDDED_ELEMENTS).expect("embedded elements database must be valid"));
 
 /// A primitive element (not necessarily simple)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Element {
 	pub molar_mass: f64,
-	pub name: String,
+	/// Element symbol, shared between all clones of the element
+	pub name: Arc<str>,
 	pub insignificant: Option<bool>,
+	/// Sort order of the element, higher goes first (see the module documentation)
 	pub priority: Option<u32>,
 	pub aliases: Option<Vec<String>>,
+	/// Group of the element (e.g. `macro` or `micro`)
+	#[serde(default)]
+	pub group: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -27,6 +52,9 @@ struct ElementData {
 	pub insignificant: Option<bool>,
 	pub priority: Option<u32>,
 	pub aliases: Option<Vec<String>>,
+	pub group: Option<String>,
+	/// Trivial names (e.g. `Nitrate`) mapped to the formula they stand for
+	pub trivial_names: Option<HashMap<String, String>>,
 }
 
 impl Element {
@@ -36,6 +64,9 @@ impl Element {
 	pub fn priority(&self) -> u32 {
 		self.priority.unwrap_or(0)
 	}
+	pub fn in_group(&self, group: &str) -> bool {
+		self.group.as_deref() == Some(group)
+	}
 }
 
 impl Hash for Element {
@@ -71,6 +102,41 @@ impl Ord for Element {
 /// Defines static knowledge of all elements we are interested in
 pub struct KnownElements {
 	pub elements: HashMap<String, Element>,
+	/// Lowercased trivial names mapped to the element name and the formula
+	trivial_names: HashMap<String, (String, String)>,
+}
+
+// Elements by their symbols rejecting duplicate symbols, as serde_json silently keeps the last one
+// whilst the TOML parser reports an error
+struct UniqueElementsData(HashMap<String, ElementData>);
+
+impl<'de> Deserialize<'de> for UniqueElementsData {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		struct ElementsVisitor;
+
+		impl<'de> Visitor<'de> for ElementsVisitor {
+			type Value = UniqueElementsData;
+
+			fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+				f.write_str("a map of elements by their symbols")
+			}
+
+			fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Self::Value, A::Error> {
+				let mut elements = HashMap::new();
+
+				while let Some((name, elt_data)) = map.next_entry::<String, ElementData>()? {
+					if elements.contains_key(&name) {
+						return Err(A::Error::custom(format!("duplicate key `{}`", name)))
+					}
+					elements.insert(name, elt_data);
+				}
+
+				Ok(UniqueElementsData(elements))
+			}
+		}
+
+		deserializer.deserialize_map(ElementsVisitor)
+	}
 }
 
 impl KnownElements {
@@ -84,9 +150,25 @@ impl KnownElements {
 
 	pub fn new_with_string(input: &str) -> Result<Self> {
 		let elements: HashMap<String, ElementData> = toml::from_str(input)?;
+
+		KnownElements::new_with_elements_data(elements)
+	}
+
+	/// Loads elements from a JSON document with the same structure as the TOML database
+	pub fn new_with_json(input: &str) -> Result<Self> {
+		let UniqueElementsData(elements) = serde_json::from_str(input)?;
+
+		KnownElements::new_with_elements_data(elements)
+	}
+
+	fn new_with_elements_data(elements: HashMap<String, ElementData>) -> Result<Self> {
+		let mut trivial_names = HashMap::new();
 		let elements = elements
 			.into_iter()
 			.map(|(name, elt_data)| {
+				for (trivial_name, formula) in elt_data.trivial_names.unwrap_or_default() {
+					trivial_names.insert(trivial_name.to_lowercase(), (name.clone(), formula));
+				}
 				(
 					name.clone(),
 					Element {
@@ -94,13 +176,79 @@ impl KnownElements {
 						priority: elt_data.priority,
 						molar_mass: elt_data.molar_mass,
 						insignificant: elt_data.insignificant,
-						name,
+						group: elt_data.group,
+						name: name.into(),
 					},
 				)
 			})
 			.collect::<HashMap<_, _>>();
 
-		Ok(Self { elements })
+		let known_elements = Self { elements, trivial_names };
+
+		for alias in known_elements.validate_alias_compounds() {
+			log::warn!("alias {} is not a valid compound of its element", alias);
+		}
+
+		Ok(known_elements)
+	}
+
+	/// Returns aliases that cannot be parsed as a compound of the corresponding element (sorted)
+	pub fn validate_alias_compounds(&self) -> Vec<String> {
+		self.elements
+			.values()
+			.flat_map(|elt| elt.aliases.iter().flatten().map(move |alias| (elt, alias)))
+			.filter(|(elt, alias)| {
+				// An alias must contain its element and something else, e.g. `P205` is just a lot of phosphorus
+				!elt.element_from_alias_rate(alias.as_str(), self).is_ok_and(|rate| rate < 1.0)
+			})
+			.map(|(_, alias)| alias.clone())
+			.sorted()
+			.collect()
+	}
+
+	/// Returns elements that are defined differently in the `other` elements database
+	pub fn overridden_elements<'a>(&'a self, other: &KnownElements) -> Vec<&'a Element> {
+		self.elements
+			.values()
+			.filter(|elt| {
+				other.elements.get(elt.name.as_ref()).is_some_and(|other_elt| {
+					elt.molar_mass != other_elt.molar_mass ||
+						elt.insignificant != other_elt.insignificant ||
+						elt.priority != other_elt.priority ||
+						elt.aliases != other_elt.aliases ||
+						elt.group != other_elt.group
+				})
+			})
+			.collect()
+	}
+
+	/// Returns all known elements sorted by priority
+	pub fn elements_by_priority(&self) -> Vec<&Element> {
+		let mut elements = self.elements.values().collect::<Vec<_>>();
+		elements.sort();
+		elements
+	}
+
+	/// Returns all elements from the specific group sorted by priority
+	pub fn elements_in_group(&self, group: &str) -> Vec<&Element> {
+		let mut elements = self.elements.values().filter(|elt| elt.in_group(group)).collect::<Vec<_>>();
+		elements.sort();
+		elements
+	}
+
+	/// Resolves an alias (either a trivial name like `Nitrate` or a formula like `NO3`) to the
+	/// element and the formula the alias stands for
+	pub fn alias_to_element(&self, alias: &str) -> Option<(&Element, &str)> {
+		if let Some((elt_name, formula)) = self.trivial_names.get(&alias.to_lowercase()) {
+			return self.elements.get(elt_name).map(|elt| (elt, formula.as_str()))
+		}
+
+		self.elements.values().find_map(|elt| {
+			elt.aliases
+				.as_ref()
+				.and_then(|aliases| aliases.iter().find(|elt_alias| elt_alias.as_str() == alias))
+				.map(|elt_alias| (elt, elt_alias.as_str()))
+		})
 	}
 }
 
@@ -117,3 +265,110 @@ impl Element {
 		self.element_from_alias_rate(alias, known_elts).map(|rate| 1.0 / rate)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assert_delta_eq, test_utils::*, traits::Fertilizer};
+
+	#[test]
+	fn alias_to_element() {
+		let known_elements = load_known_elements();
+		let (elt, formula) = known_elements.alias_to_element("Nitrate").unwrap();
+		assert_eq!(elt.name.as_ref(), "N");
+		assert_eq!(formula, "NO3");
+		let (elt, formula) = known_elements.alias_to_element("potash").unwrap();
+		assert_eq!(elt.name.as_ref(), "K");
+		assert_delta_eq!(elt.element_from_alias_rate(formula, &known_elements).unwrap(), 0.830, MOLAR_MASS_EPSILON);
+		let (elt, formula) = known_elements.alias_to_element("PO4").unwrap();
+		assert_eq!(elt.name.as_ref(), "P");
+		assert_eq!(formula, "PO4");
+		assert!(known_elements.alias_to_element("Ololo").is_none());
+	}
+
+	#[test]
+	fn elements_in_group() {
+		let known_elements = load_known_elements();
+		let macro_elements = known_elements.elements_in_group("macro");
+		assert_eq!(macro_elements.iter().take(3).map(|elt| elt.name.as_ref()).collect::<Vec<_>>(), vec!["N", "P", "K"]);
+		assert!(known_elements
+			.elements_in_group("micro")
+			.iter()
+			.any(|elt| elt.name.as_ref() == "Fe"));
+		assert!(known_elements.elements_in_group("Ololo").is_empty());
+	}
+
+	#[test]
+	fn validate_alias_compounds() {
+		let known_elements = load_known_elements();
+		assert!(known_elements.validate_alias_compounds().is_empty());
+		let custom_elements = KnownElements::new_with_string(
+			"[N]\nmolar_mass = 14.007\naliases = [\"NO3\", \"N2\", \"Ololo\"]\n[O]\nmolar_mass = 15.999\n",
+		)
+		.unwrap();
+		assert_eq!(custom_elements.validate_alias_compounds(), vec!["N2", "Ololo"]);
+	}
+
+	#[test]
+	fn load_json() {
+		let known_elements = KnownElements::new_with_json(
+			r#"{"N": {"molar_mass": 14.007, "aliases": ["NO3"], "priority": 1}, "O": {"molar_mass": 15.999}}"#,
+		)
+		.unwrap();
+		let nitrogen = known_elements.elements.get("N").unwrap();
+		assert_delta_eq!(nitrogen.molar_mass, 14.007, MOLAR_MASS_EPSILON);
+		assert_eq!(nitrogen.aliases.as_ref().unwrap(), &vec!["NO3".to_owned()]);
+		assert!(KnownElements::new_with_json("[N]\nmolar_mass = 14.007\n").is_err());
+	}
+
+	#[test]
+	fn duplicate_elements() {
+		let err = KnownElements::new_with_string("[N]\nmolar_mass = 14.007\n[N]\nmolar_mass = 14.0\n")
+			.err()
+			.unwrap()
+			.to_string();
+		assert!(err.contains("duplicate key `N`"), "{}", err);
+		let err = KnownElements::new_with_json(r#"{"N": {"molar_mass": 14.007}, "N": {"molar_mass": 14.0}}"#)
+			.err()
+			.unwrap()
+			.to_string();
+		assert!(err.contains("duplicate key `N`"), "{}", err);
+	}
+
+	#[test]
+	fn priority_order() {
+		let known_elements = load_known_elements();
+		let compound = Compound::new("FeNH4(SO4)2", &known_elements).unwrap();
+		let names = compound
+			.components_percentage(&known_elements)
+			.iter()
+			.map(|elt| elt.element.name.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(names, vec!["N", "S", "Fe"]);
+	}
+
+	#[test]
+	fn elements_by_priority() {
+		let known_elements = load_known_elements();
+		let names = known_elements
+			.elements_by_priority()
+			.iter()
+			.map(|elt| elt.name.as_ref())
+			.collect::<Vec<&str>>();
+		assert_eq!(names.len(), known_elements.elements.len());
+		assert_eq!(names[..4], ["N", "P", "K", "Mg"]);
+		assert_eq!(names.last(), Some(&"O"));
+	}
+
+	#[test]
+	fn overridden_elements() {
+		let known_elements = load_known_elements();
+		let custom_elements = KnownElements::new_with_string(
+			"[N]\nmolar_mass = 14.0\n[K]\nmolar_mass = 39.0983\naliases = [\"K2O\"]\npriority = 80\ngroup = \"macro\"\n",
+		)
+		.unwrap();
+		let overridden = custom_elements.overridden_elements(&known_elements);
+		assert_eq!(overridden.len(), 1);
+		assert_eq!(overridden[0].name.as_ref(), "N");
+	}
+}
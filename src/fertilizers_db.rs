@@ -1,43 +1,481 @@
 use crate::{compound::Compound, elements::KnownElements, mix::MixedFertilizer, Fertilizer};
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt::{Display, Formatter},
+	fs,
+	path::{Path, PathBuf},
+};
 
-/// All known fertilizers indexed by their name
-#[derive(Default)]
+/// Name of the table with the database metadata
+const META_SECTION: &str = "meta";
+/// Name of the table that maps fertilizer names to lists of category tags
+const CATEGORIES_SECTION: &str = "categories";
+/// Keys recognised in a fertilizer definition, others are most likely typos
+const KNOWN_FERTILIZER_KEYS: [&str; 6] =
+	["compounds", "formula", "description", "percents", "manufacturer", "solubility"];
+
+/// Optional description of the fertilizers database from the `[meta]` section
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DbMetadata {
+	pub version: String,
+	pub author: String,
+	pub description: String,
+}
+
+impl Display for DbMetadata {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		// Any of the fields might be missing in the `[meta]` section
+		let details = [("version ", &self.version), ("by ", &self.author)]
+			.into_iter()
+			.filter(|(_, value)| !value.is_empty())
+			.map(|(prefix, value)| format!("{}{}", prefix, value))
+			.collect::<Vec<_>>();
+		f.write_str(self.description.as_str())?;
+
+		if !details.is_empty() {
+			let separator = if self.description.is_empty() { "" } else { " " };
+			write!(f, "{}({})", separator, details.join(", "))?;
+		}
+
+		Ok(())
+	}
+}
+
+/// All known fertilizers indexed by their name (sorted by name)
+#[derive(Default, Clone)]
 pub struct FertilizersDb {
-	pub known_fertilizers: HashMap<String, Box<dyn Fertilizer + Send>>,
+	pub known_fertilizers: BTreeMap<String, Box<dyn Fertilizer + Send>>,
+	metadata: Option<DbMetadata>,
+	/// Files the fertilizers were loaded from, embedded fertilizers are not listed
+	sources: BTreeMap<String, PathBuf>,
+	/// Category tags of the fertilizers from the `[categories]` section
+	categories: HashMap<String, Vec<String>>,
+	/// Names of the compound based entries by their composition
+	formulas: HashMap<Compound, BTreeSet<String>>,
+}
+
+// Fertilizers are not `Debug`, so only their names are printed
+impl std::fmt::Debug for FertilizersDb {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("FertilizersDb")
+			.field("count", &self.known_fertilizers.len())
+			.field("names", &self.known_fertilizers.keys().collect::<Vec<_>>())
+			.field("metadata", &self.metadata)
+			.finish()
+	}
 }
 
 impl FertilizersDb {
+	/// Load fertilizers from a TOML database
 	pub fn load_db(&mut self, input: &str, known_elts: &KnownElements) -> Result<()> {
-		let res: toml::Value = toml::from_str(input)?;
+		let res: toml::Value = toml::from_str(input).map_err(|e| {
+			let line = e.span().map(|span| line_at_offset(input, span.start));
+			let err = anyhow::Error::new(e);
+
+			match line {
+				Some(line) => err.context(format!("cannot parse fertilizers database at line {}", line)),
+				None => err,
+			}
+		})?;
+		self.load_db_value(&res, known_elts)
+	}
+
+	/// Load fertilizers from a YAML database, the structure is the same as for TOML
+	pub fn load_db_yaml(&mut self, input: &str, known_elts: &KnownElements) -> Result<()> {
+		let res: serde_yaml::Value = serde_yaml::from_str(input)?;
+		let res = toml::Value::try_from(res)?;
+		self.load_db_value(&res, known_elts)
+	}
+
+	/// Load fertilizers from a TOML or YAML (detected by the extension) file remembering the source path
+	pub fn load_db_file(&mut self, path: &Path, known_elts: &KnownElements) -> Result<()> {
+		let data = fs::read_to_string(path).with_context(|| format!("cannot read {:?}", path))?;
+		let is_yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+		let mut file_db: FertilizersDb = Default::default();
+
+		if is_yaml {
+			file_db.load_db_yaml(data.as_str(), known_elts)
+		} else {
+			file_db.load_db(data.as_str(), known_elts)
+		}
+		.with_context(|| format!("cannot load fertilizers database {:?}", path))?;
+
+		for (name, fertilizer) in file_db.known_fertilizers {
+			self.sources.insert(name.clone(), path.to_path_buf());
+			self.insert_fertilizer(name, fertilizer);
+		}
+		if file_db.metadata.is_some() {
+			self.metadata = file_db.metadata;
+		}
+		self.categories.extend(file_db.categories);
+
+		Ok(())
+	}
+
+	/// Returns category tags of the specific fertilizer (empty if there are none)
+	pub fn categories_of(&self, name: &str) -> &[String] {
+		self.categories.get(name).map_or(&[], Vec::as_slice)
+	}
+
+	/// Returns a file the specific fertilizer was loaded from
+	pub fn source(&self, name: &str) -> Option<&Path> {
+		self.sources.get(name).map(PathBuf::as_path)
+	}
+
+	/// Returns metadata of the last loaded database that has the `[meta]` section
+	pub fn metadata(&self) -> Option<&DbMetadata> {
+		self.metadata.as_ref()
+	}
+
+	/// Number of the known fertilizers
+	pub fn len(&self) -> usize {
+		self.known_fertilizers.len()
+	}
+
+	/// Returns true if no fertilizers are loaded
+	pub fn is_empty(&self) -> bool {
+		self.known_fertilizers.is_empty()
+	}
+
+	/// Returns a database entry for the specific formula, compound based entries are compared by their
+	/// composition (e.g. `Urea` is found by `CH4N2O` or `CO(NH2)2`), mixes are never matched
+	pub fn get_by_formula(&self, formula: &str, known_elts: &KnownElements) -> Option<(&str, &dyn Fertilizer)> {
+		let compound = Compound::new(formula, known_elts).ok()?;
+		// Names are sorted, so the result is stable if several entries share the same composition
+		let name = self.formulas.get(&compound)?.first()?;
+
+		self.known_fertilizers
+			.get_key_value(name)
+			.map(|(name, fertilizer)| (name.as_str(), &**fertilizer as &dyn Fertilizer))
+	}
+
+	// Adds or replaces an entry keeping the formulas index in sync
+	fn insert_fertilizer(&mut self, name: String, fertilizer: Box<dyn Fertilizer + Send>) {
+		self.unindex_formula(name.as_str());
+		if let Some(compound) = fertilizer.as_compound() {
+			self.formulas.entry(compound.clone()).or_default().insert(name.clone());
+		}
+		self.known_fertilizers.insert(name, fertilizer);
+	}
+
+	// Removes an entry from the formulas index
+	fn unindex_formula(&mut self, name: &str) {
+		if let Some(compound) = self.known_fertilizers.get(name).and_then(|fertilizer| fertilizer.as_compound()) {
+			if let Some(names) = self.formulas.get_mut(compound) {
+				names.remove(name);
+				if names.is_empty() {
+					self.formulas.remove(compound);
+				}
+			}
+		}
+	}
+
+	/// Replaces an existing fertilizer, returns an error if there is no fertilizer with this name
+	pub fn update_fertilizer(&mut self, name: &str, new_fertilizer: Box<dyn Fertilizer + Send>) -> Result<()> {
+		if !self.known_fertilizers.contains_key(name) {
+			return Err(anyhow!("fertilizer {} is not found", name))
+		}
+		self.insert_fertilizer(name.to_owned(), new_fertilizer);
+		// The entry is not the one from the database file anymore
+		self.sources.remove(name);
+		self.categories.remove(name);
 
+		Ok(())
+	}
+
+	/// Removes a fertilizer returning it if it has been found
+	pub fn remove_fertilizer(&mut self, name: &str) -> Option<Box<dyn Fertilizer + Send>> {
+		self.sources.remove(name);
+		self.categories.remove(name);
+		self.unindex_formula(name);
+		self.known_fertilizers.remove(name)
+	}
+
+	/// Returns the fertilizer with the highest fraction of the specific element
+	pub fn find_best_source_for_element(&self, symbol: &str, known_elts: &KnownElements) -> Option<(&str, f64)> {
+		self.known_fertilizers
+			.iter()
+			.filter_map(|(name, fertilizer)| {
+				fertilizer
+					.components_percentage(known_elts)
+					.into_iter()
+					.find(|elt| elt.element.name.as_ref() == symbol)
+					.map(|elt| (name.as_str(), elt.concentration))
+			})
+			.max_by(|(_, fraction1), (_, fraction2)| fraction1.total_cmp(fraction2))
+	}
+
+	fn load_db_value(&mut self, res: &toml::Value, known_elts: &KnownElements) -> Result<()> {
 		if !res.is_table() {
 			return Err(anyhow!("known fertilizers must be an object"))
 		}
 
 		for (name, obj) in res.as_table().unwrap().iter() {
+			if name == META_SECTION {
+				let metadata: DbMetadata = obj.clone().try_into().context("invalid database metadata")?;
+				self.metadata = Some(metadata);
+				continue
+			}
+
+			if name == CATEGORIES_SECTION {
+				let categories: HashMap<String, Vec<String>> =
+					obj.clone().try_into().context("categories must be lists of strings")?;
+				self.categories.extend(categories);
+				continue
+			}
+
 			if !obj.is_table() {
 				return Err(anyhow!("fertilizer {} is not an object", name))
 			}
 
 			let fert_obj = obj.as_table().unwrap();
 
+			for key in unknown_fertilizer_keys(fert_obj) {
+				log::warn!("Unknown key '{}' in fertilizer '{}'", key, name);
+			}
+
 			if fert_obj.contains_key("compounds") {
-				let mix = Box::new(MixedFertilizer::new_from_toml_object(name.as_str(), obj, known_elts, true)?);
-				self.known_fertilizers.insert(name.clone(), mix as Box<dyn Fertilizer + Send>);
+				let mix = Box::new(
+					MixedFertilizer::new_from_toml_object(name.as_str(), obj, known_elts, true)
+						.with_context(|| format!("invalid fertilizer {}", name))?,
+				);
+				mix.validate(known_elts)?;
+				self.insert_fertilizer(name.clone(), mix);
 			} else if fert_obj.contains_key("formula") {
 				let formula = fert_obj
 					.get("formula")
 					.unwrap()
 					.as_str()
 					.ok_or_else(|| anyhow!("formula must be string in {}", name))?;
-				let compound = Box::new(Compound::new(formula, known_elts)?);
-				self.known_fertilizers
-					.insert(name.clone(), compound as Box<dyn Fertilizer + Send>);
+				let compound = Box::new(
+					Compound::new(formula, known_elts).with_context(|| format!("invalid fertilizer {}", name))?,
+				);
+				compound.validate(known_elts)?;
+				self.insert_fertilizer(name.clone(), compound);
 			}
 		}
 
 		Ok(())
 	}
 }
+
+// Returns keys of a fertilizer definition that are not used by the loader
+fn unknown_fertilizer_keys(fert_obj: &toml::Table) -> Vec<&str> {
+	fert_obj
+		.keys()
+		.map(String::as_str)
+		.filter(|key| !KNOWN_FERTILIZER_KEYS.contains(key))
+		.collect()
+}
+
+// Converts a byte offset to a line number (starting from 1)
+fn line_at_offset(input: &str, offset: usize) -> usize {
+	input.as_bytes().iter().take(offset).filter(|&&c| c == b'\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assert_delta_eq, test_utils::*};
+
+	fn sample_db_yaml() -> &'static str {
+		r#"
+Miracle Gro:
+  compounds:
+    N: 24
+    P2O5: 8
+    K2O: 16
+Urea:
+  formula: CH4N2O
+"#
+	}
+
+	#[test]
+	fn load_yaml() {
+		let known_elements = load_known_elements();
+		let mut yaml_db: FertilizersDb = Default::default();
+		yaml_db.load_db_yaml(sample_db_yaml(), &known_elements).unwrap();
+		let toml_db = load_known_fertilizers(&known_elements);
+		assert_eq!(yaml_db.known_fertilizers.len(), 2);
+
+		for name in ["Miracle Gro", "Urea"] {
+			let yaml_percentages = yaml_db.known_fertilizers[name].components_percentage(&known_elements);
+			let toml_percentages = toml_db.known_fertilizers[name].components_percentage(&known_elements);
+			// Micro elements are omitted in the yaml sample
+			for (yaml_elt, toml_elt) in yaml_percentages.iter().zip(toml_percentages.iter()).take(3) {
+				assert_eq!(yaml_elt.element, toml_elt.element);
+				assert_delta_eq!(yaml_elt.concentration, toml_elt.concentration, MOLAR_MASS_EPSILON);
+			}
+		}
+	}
+
+	#[test]
+	fn load_error_line() {
+		let known_elements = load_known_elements();
+		let mut fertilizers_db: FertilizersDb = Default::default();
+		let err = fertilizers_db
+			.load_db("[Urea]\nformula = \"CH4N2O\"\n[Broken\n", &known_elements)
+			.unwrap_err();
+		assert!(err.to_string().contains("line 3"), "{}", err);
+	}
+
+	#[test]
+	fn clone_and_debug() {
+		let known_elements = load_known_elements();
+		let fertilizers = load_known_fertilizers(&known_elements);
+		let mut cloned = fertilizers.clone();
+		cloned.known_fertilizers.remove("Urea");
+		assert!(fertilizers.known_fertilizers.contains_key("Urea"));
+		assert_eq!(cloned.len() + 1, fertilizers.len());
+		assert!(!cloned.is_empty() && FertilizersDb::default().is_empty());
+		let debug = format!("{:?}", fertilizers);
+		assert!(debug.contains("\"Urea\""), "{}", debug);
+		assert!(debug.contains(format!("count: {}", fertilizers.len()).as_str()), "{}", debug);
+	}
+
+	#[test]
+	fn update_and_remove() {
+		let known_elements = load_known_elements();
+		let mut fertilizers = load_known_fertilizers(&known_elements);
+		let kno3 = Box::new(Compound::new("KNO3", &known_elements).unwrap());
+		fertilizers.update_fertilizer("Urea", kno3.clone()).unwrap();
+		assert_eq!(fertilizers.known_fertilizers["Urea"].name(), "KNO3");
+		// Formulas index follows the replaced entry
+		assert!(fertilizers.get_by_formula("CO(NH2)2", &known_elements).is_none());
+		assert_eq!(fertilizers.get_by_formula("NO3K", &known_elements).unwrap().0, "Urea");
+		assert!(fertilizers.update_fertilizer("Ololo", kno3).is_err());
+		assert!(!fertilizers.known_fertilizers.contains_key("Ololo"));
+		let removed = fertilizers.remove_fertilizer("Urea").unwrap();
+		assert_eq!(removed.name(), "KNO3");
+		assert!(fertilizers.remove_fertilizer("Urea").is_none());
+		assert!(fertilizers.get_by_formula("KNO3", &known_elements).is_none());
+
+		// Category tags of the replaced or removed entries are dropped as well
+		fertilizers
+			.load_db("[categories]\n\"Miracle Gro\" = [\"npk\"]\n\"Solufeed TEC\" = [\"micro\"]\n", &known_elements)
+			.unwrap();
+		let kno3 = Box::new(Compound::new("KNO3", &known_elements).unwrap());
+		fertilizers.update_fertilizer("Miracle Gro", kno3).unwrap();
+		assert!(fertilizers.categories_of("Miracle Gro").is_empty());
+		assert_eq!(fertilizers.categories_of("Solufeed TEC"), ["micro"]);
+		fertilizers.remove_fertilizer("Solufeed TEC").unwrap();
+		assert!(fertilizers.categories_of("Solufeed TEC").is_empty());
+	}
+
+	#[test]
+	fn load_categories() {
+		let known_elements = load_known_elements();
+		let mut fertilizers = load_known_fertilizers(&known_elements);
+		fertilizers
+			.load_db("[KNO3]\nformula = \"KNO3\"\n[categories]\nKNO3 = [\"macro\", \"nitrogen\"]\n", &known_elements)
+			.unwrap();
+		assert_eq!(fertilizers.categories_of("KNO3"), ["macro", "nitrogen"]);
+		assert!(fertilizers.categories_of("Urea").is_empty());
+		assert!(!fertilizers.known_fertilizers.contains_key(CATEGORIES_SECTION));
+		assert!(fertilizers
+			.load_db("[categories]\nKNO3 = \"macro\"\n", &known_elements)
+			.is_err());
+	}
+
+	#[test]
+	fn unknown_keys() {
+		let obj: toml::Table =
+			toml::from_str("formula = \"KNO3\"\ncolor = \"blue\"\ndescriptoin = \"typo\"\n").unwrap();
+		assert_eq!(unknown_fertilizer_keys(&obj), vec!["color", "descriptoin"]);
+	}
+
+	#[test]
+	fn load_metadata() {
+		let known_elements = load_known_elements();
+		let mut fertilizers_db: FertilizersDb = Default::default();
+		assert!(fertilizers_db.metadata().is_none());
+		fertilizers_db
+			.load_db(
+				r#"
+				[meta]
+				version = "1.0"
+				author = "Test"
+				description = "Sample database"
+
+				[Urea]
+				formula = "CH4N2O"
+				"#,
+				&known_elements,
+			)
+			.unwrap();
+		assert_eq!(fertilizers_db.known_fertilizers.len(), 1);
+		let metadata = fertilizers_db.metadata().unwrap();
+		assert_eq!(metadata.version, "1.0");
+		assert_eq!(metadata.to_string(), "Sample database (version 1.0, by Test)");
+		let partial = DbMetadata { author: String::new(), ..metadata.clone() };
+		assert_eq!(partial.to_string(), "Sample database (version 1.0)");
+		let partial = DbMetadata { version: String::new(), description: String::new(), ..metadata.clone() };
+		assert_eq!(partial.to_string(), "(by Test)");
+		let description_only = DbMetadata { description: "Sample database".into(), ..Default::default() };
+		assert_eq!(description_only.to_string(), "Sample database");
+	}
+
+	#[test]
+	fn get_by_formula() {
+		let known_elements = load_known_elements();
+		let fertilizers_db = load_known_fertilizers(&known_elements);
+		let (name, fertilizer) = fertilizers_db.get_by_formula("CH4N2O", &known_elements).unwrap();
+		assert_eq!(name, "Urea");
+		assert_eq!(fertilizer.name(), "CH4N2O");
+		let (name, _) = fertilizers_db.get_by_formula("CO(NH2)2", &known_elements).unwrap();
+		assert_eq!(name, "Urea");
+		assert!(fertilizers_db.get_by_formula("KNO3", &known_elements).is_none());
+		// Mixes are not matched even if they consist of a single compound
+		let mut fertilizers_db: FertilizersDb = Default::default();
+		fertilizers_db
+			.load_db("[\"Pure KNO3\".compounds]\nKNO3 = 100.0\n", &known_elements)
+			.unwrap();
+		assert!(fertilizers_db.get_by_formula("KNO3", &known_elements).is_none());
+	}
+
+	#[test]
+	fn micro_elements() {
+		let known_elements = load_known_elements();
+		let mut fertilizers_db: FertilizersDb = Default::default();
+		fertilizers_db
+			.load_db("[\"Fe DTPA\"]\nformula = \"C14H18N3O10FeNa2\"\n", &known_elements)
+			.unwrap();
+		let iron = fertilizers_db.known_fertilizers["Fe DTPA"]
+			.components_percentage(&known_elements)
+			.into_iter()
+			.find(|elt| elt.element.name.as_ref() == "Fe")
+			.unwrap();
+		assert!(iron.element.in_group("micro"));
+		assert_delta_eq!(iron.concentration, 0.1139, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn load_file_source() {
+		let known_elements = load_known_elements();
+		let mut fertilizers_db: FertilizersDb = Default::default();
+		fertilizers_db
+			.load_db("[\"Tomato feed\".compounds]\nN = 4\n", &known_elements)
+			.unwrap();
+		fertilizers_db
+			.load_db_file(Path::new("./fertilizers.toml"), &known_elements)
+			.unwrap();
+		assert!(fertilizers_db.source("Tomato feed").is_none());
+		assert_eq!(fertilizers_db.source("Urea"), Some(Path::new("./fertilizers.toml")));
+		assert!(fertilizers_db
+			.load_db_file(Path::new("./missing.toml"), &known_elements)
+			.is_err());
+	}
+
+	#[test]
+	fn best_source() {
+		let known_elements = load_known_elements();
+		let fertilizers_db = load_known_fertilizers(&known_elements);
+		let (name, fraction) = fertilizers_db.find_best_source_for_element("N", &known_elements).unwrap();
+		assert_eq!(name, "Urea");
+		assert_delta_eq!(fraction, 0.4665, MOLAR_MASS_EPSILON);
+		assert!(fertilizers_db.find_best_source_for_element("Ni", &known_elements).is_none());
+	}
+}
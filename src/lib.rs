@@ -0,0 +1,20 @@
+//! A simple calculator for DIY aquarium fertilisers
+
+pub mod compound;
+pub mod concentration;
+pub mod elements;
+pub mod fertilizers_db;
+pub mod mix;
+pub mod tank;
+pub mod traits;
+pub mod units;
+
+#[cfg(test)]
+#[macro_use]
+mod test_utils;
+pub mod web;
+
+pub use crate::{
+	fertilizers_db::FertilizersDb,
+	traits::{DiluteMethod, Fertilizer},
+};
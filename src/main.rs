@@ -1,9 +1,9 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use crossterm::style::Stylize;
-use itertools::Itertools;
 use rustyline::{
 	completion::{Completer, Pair},
+	error::ReadlineError,
 	highlight::Highlighter,
 	hint::Hinter,
 	validate::Validator,
@@ -13,31 +13,38 @@ use std::{
 	fs,
 	net::SocketAddr,
 	path::PathBuf,
-	sync::{Arc, Mutex},
+	sync::{Arc, Mutex, RwLock},
 };
 
-use crate::{
+use fert_calc::{
+	compound, concentration,
+	elements::{self, Element, KnownElements},
 	fertilizers_db::FertilizersDb,
-	traits::{DiluteMethod, Fertilizer},
+	mix, tank,
+	traits::{self, DiluteMethod, Fertilizer},
+	web,
 };
 
-mod compound;
-mod concentration;
-mod elements;
-mod fertilizers_db;
-mod mix;
-mod tank;
-mod traits;
-
-#[cfg(test)]
-#[macro_use]
-mod test_utils;
-mod web;
+/// Embedded fertilizers database
+const EMBEDDED_FERTILIZERS: &str = include_str!("../fertilizers.toml");
+
+// Prints informational text to stdout for the pretty output and to stderr otherwise,
+// so machine readable results on stdout stay parseable
+macro_rules! info_println {
+	($opts:expr, $($arg:tt)*) => {
+		if $opts.output == OutputFormat::Pretty {
+			println!($($arg)*)
+		} else {
+			eprintln!($($arg)*)
+		}
+	};
+}
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, clap::ValueEnum)]
 enum TankInputMode {
 	Linear,
 	Volume,
+	HalfSphere,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, clap::ValueEnum)]
@@ -57,6 +64,41 @@ enum FertilizerType {
 enum CalculationType {
 	Dose,
 	Target,
+	WeeklyEI,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, clap::ValueEnum)]
+enum SortBy {
+	Priority,
+	Concentration,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+	Pretty,
+	Plain,
+	Json,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+	Error,
+	Warn,
+	Info,
+	Debug,
+	Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+	fn from(level: LogLevel) -> Self {
+		match level {
+			LogLevel::Error => log::LevelFilter::Error,
+			LogLevel::Warn => log::LevelFilter::Warn,
+			LogLevel::Info => log::LevelFilter::Info,
+			LogLevel::Debug => log::LevelFilter::Debug,
+			LogLevel::Trace => log::LevelFilter::Trace,
+		}
+	}
 }
 
 impl From<CalculationType> for concentration::DiluteCalcType {
@@ -64,6 +106,7 @@ impl From<CalculationType> for concentration::DiluteCalcType {
 		match ct {
 			CalculationType::Dose => concentration::DiluteCalcType::ResultOfDose,
 			CalculationType::Target => concentration::DiluteCalcType::TargetDose,
+			CalculationType::WeeklyEI => concentration::DiluteCalcType::WeeklyEI,
 		}
 	}
 }
@@ -104,23 +147,31 @@ impl Completer for FertInputHelper {
 }
 
 #[derive(Debug, Parser)]
+#[clap(version)]
 pub(crate) struct Opts {
 	/// Path to the elements toml database to use instead of the embedded one
 	#[clap(long)]
 	elements: Option<PathBuf>,
+	/// Path to the elements json database to use instead of the embedded one
+	#[clap(long, conflicts_with = "elements")]
+	elements_json: Option<PathBuf>,
 	/// How a tank data is added
 	#[clap(long, value_enum, default_value = "volume")]
 	tank_input: TankInputMode,
 	/// Optional path for a toml file with tank definition
 	#[clap(long)]
 	tank_toml: Option<PathBuf>,
+	/// Optional path for a yaml file with tank definition
+	#[clap(long, conflicts_with = "tank_toml")]
+	tank_yaml: Option<PathBuf>,
 	/// How a fertiliser is added
 	#[clap(long, value_enum, default_value = "dry")]
 	dosing_method: DosingMethod,
 	/// What type of fertilizer is checked
 	#[clap(long, value_enum, default_value = "any")]
 	fertilizer: FertilizerType,
-	/// Path to fertilizers database in toml format instead of the embedded database
+	/// Path to fertilizers database in toml or yaml format (detected by the file extension) in addition to the
+	/// embedded database
 	#[clap(long)]
 	database: Vec<PathBuf>,
 	/// What type of calculation is desired
@@ -129,56 +180,198 @@ pub(crate) struct Opts {
 	/// List the available fertilizers loaded from the database and exit
 	#[clap(long, short = 'l')]
 	list: bool,
+	/// List only fertilizers with the specific category tag from the database `[categories]` section
+	#[clap(long, requires = "list")]
+	category: Option<String>,
+	/// Show more details, e.g. database files of the listed fertilizers
+	#[clap(long, short = 'v')]
+	verbose: bool,
+	/// Print version, build details and embedded databases statistics (useful for bug reports)
+	#[clap(long)]
+	build_info: bool,
+	/// Show the fertilizer with the highest fraction of the specific element and exit
+	#[clap(long)]
+	best_source_for: Option<String>,
 	/// Use absolute volume without corrections
 	#[clap(long, short = 'a')]
 	absolute: bool,
+	/// Dose only for the new water after a partial water change of the specific percentage
+	#[clap(long)]
+	water_change: Option<f64>,
 	/// Work as a web server
 	#[clap(long, short = 's')]
 	serve: Option<SocketAddr>,
 	/// Add static directory to serve
 	#[clap(long)]
 	static_dir: Option<String>,
+	/// Maximum size of a JSON request body in bytes for the web server
+	#[clap(long, value_name = "BYTES", default_value_t = web::DEFAULT_MAX_BODY_SIZE)]
+	max_body_size: usize,
+	/// Access log format of the web server: `default` or a custom actix-web format string (e.g. "%a %r %s %T")
+	#[clap(long, default_value = web::DEFAULT_LOG_FORMAT)]
+	log_format: String,
+	/// Verbosity of the diagnostic messages [default: `info` with --serve to show the access log, `warn` otherwise]
+	#[clap(long, value_enum)]
+	log_level: Option<LogLevel>,
+	/// How the calculation results are displayed
+	#[clap(long, value_enum, default_value = "pretty")]
+	output: OutputFormat,
+	/// How the resulting doses by elements are ordered
+	#[clap(long, value_enum, default_value = "priority")]
+	sort_by: SortBy,
+	/// Also display insignificant elements (e.g. H or O) in the fertilizer composition
+	#[clap(long)]
+	show_all_elements: bool,
+	/// Display only elements from the specific group (e.g. macro or micro)
+	#[clap(long)]
+	element_group: Option<String>,
+}
+
+// Checks if an element should be displayed according to the group filter
+fn is_displayed(element: &Element, group: Option<&str>) -> bool {
+	group.is_none_or(|group| element.in_group(group))
+}
+
+fn print_components(fertilizer: &dyn Fertilizer, known_elements: &KnownElements, opts: &Opts) {
+	let components = if opts.show_all_elements {
+		fertilizer.components_percentage_all(known_elements)
+	} else {
+		fertilizer.components_percentage(known_elements)
+	};
+	info_println!(opts, "Compounds by elements");
+
+	for displayed_elt in components
+		.iter()
+		.filter(|elt| is_displayed(&elt.element, opts.element_group.as_deref()))
+	{
+		info_println!(opts, "{:?}", displayed_elt);
+	}
+}
+
+// Prints information to identify the specific build
+fn print_build_info() -> Result<()> {
+	let mut fertilizers_db: FertilizersDb = Default::default();
+	fertilizers_db.load_db(EMBEDDED_FERTILIZERS, &elements::GLOBAL_KNOWN_ELEMENTS)?;
+
+	println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+	println!("Rust edition: {}", env!("FERT_CALC_EDITION"));
+	println!("Build timestamp: {} (unix time)", env!("FERT_CALC_BUILD_TIMESTAMP"));
+	println!("Embedded elements: {}", elements::GLOBAL_KNOWN_ELEMENTS.elements.len());
+	println!("Embedded fertilizers: {}", fertilizers_db.len());
+
+	Ok(())
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
 	let opts = Opts::parse();
+	// Web server access log is written at the info level, so it is visible by default
+	let default_log_level = if opts.serve.is_some() { LogLevel::Info } else { LogLevel::Warn };
+	env_logger::Builder::new()
+		.filter_level(opts.log_level.unwrap_or(default_log_level).into())
+		.init();
+
+	// Ctrl+C at any interactive prompt is a normal way to leave
+	run(opts).await.or_else(|err| match err.downcast_ref::<ReadlineError>() {
+		Some(ReadlineError::Interrupted) => {
+			println!("Interrupted.");
+			std::process::exit(0)
+		},
+		_ => Err(err),
+	})
+}
+
+async fn run(opts: Opts) -> Result<()> {
+	if opts.build_info {
+		return print_build_info()
+	}
 
-	let known_elements = if let Some(elts_path) = opts.elements {
-		elements::KnownElements::new_with_db(elts_path.as_path())
+	// Avoid hassle for generic users
+	let known_elements_toml = elements::EMBEDDED_ELEMENTS;
+	let custom_elements_path = opts.elements.as_ref().or(opts.elements_json.as_ref());
+	let known_elements = if let Some(elts_path) = custom_elements_path {
+		let custom_elements = if opts.elements_json.is_some() {
+			elements::KnownElements::new_with_json(fs::read_to_string(elts_path)?.as_str())?
+		} else {
+			elements::KnownElements::new_with_db(elts_path.as_path())?
+		};
+		let builtin_elements = elements::KnownElements::new_with_string(known_elements_toml)?;
+
+		for elt in custom_elements.overridden_elements(&builtin_elements) {
+			log::warn!("element {} from {:?} overrides the built-in definition", elt.name, elts_path);
+		}
+
+		custom_elements
 	} else {
-		// Avoid hassle for generic users
-		let known_elements_toml = include_str!("../elements.toml");
-		elements::KnownElements::new_with_string(known_elements_toml)
-	}?;
+		elements::KnownElements::new_with_string(known_elements_toml)?
+	};
 
 	let mut fertilizers_db: FertilizersDb = Default::default();
 
-	let known_fertilizers_toml = include_str!("../fertilizers.toml");
-	fertilizers_db.load_db(known_fertilizers_toml, &known_elements)?;
+	fertilizers_db.load_db(EMBEDDED_FERTILIZERS, &known_elements)?;
 
 	for extra_db in opts.database.iter() {
-		let data = fs::read_to_string(extra_db.as_path())?;
-		fertilizers_db.load_db(data.as_str(), &known_elements)?;
+		fertilizers_db.load_db_file(extra_db.as_path(), &known_elements)?;
 	}
 
 	if opts.list {
-		for fert_name in fertilizers_db.known_fertilizers.keys().sorted() {
-			println!("{}", fert_name);
+		let listed = fertilizers_db
+			.known_fertilizers
+			.keys()
+			.filter(|name| {
+				opts.category
+					.as_ref()
+					.is_none_or(|category| fertilizers_db.categories_of(name).contains(category))
+			})
+			.collect::<Vec<_>>();
+
+		if opts.output == OutputFormat::Json {
+			let envelope = serde_json::json!({
+				"database": fertilizers_db.metadata(),
+				"fertilizers": listed,
+				"total": listed.len(),
+			});
+			println!("{}", serde_json::to_string_pretty(&envelope)?);
+			return Ok(())
+		}
+
+		if let Some(metadata) = fertilizers_db.metadata() {
+			println!("Database: {}", metadata);
+		}
+
+		for fert_name in listed.iter() {
+			match fertilizers_db.source(fert_name).filter(|_| opts.verbose) {
+				Some(source) => println!("{} ({})", fert_name, source.display()),
+				None => println!("{}", fert_name),
+			}
+		}
+
+		println!("Total: {} fertilizers loaded.", listed.len());
+
+		return Ok(())
+	}
+
+	if let Some(symbol) = &opts.best_source_for {
+		match fertilizers_db.find_best_source_for_element(symbol.as_str(), &known_elements) {
+			Some((name, fraction)) => println!("Best source of {}: {} ({:.2}%)", symbol, name.bold(), fraction * 100.0),
+			None => println!("No fertilizer contains {}", symbol),
 		}
 
 		return Ok(())
 	}
 
 	if let Some(listen_addr) = opts.serve {
-		return web::run_server(
+		let (server, addrs) = web::run_server(
 			Arc::new(Mutex::new(fertilizers_db)),
-			Arc::new(Mutex::new(known_elements)),
+			Arc::new(RwLock::new(known_elements)),
 			listen_addr,
 			opts.static_dir.clone(),
+			opts.max_body_size,
+			opts.log_format.clone(),
 		)
-		.await
-		.map_err(|e| anyhow!("server error: {:?}", e))
+		.map_err(|e| anyhow!("server error: {:?}", e))?;
+		log::info!("listening on {:?}", addrs);
+		return server.await.map_err(|e| anyhow!("server error: {:?}", e))
 	}
 
 	let config = rustyline::Config::builder()
@@ -199,50 +392,36 @@ async fn main() -> Result<()> {
 
 			match maybe_known_fertilizer {
 				Some(fertilizer_box) => {
-					println!("Fertilizer: {}", fertilizer_box.name().bold());
-					println!("Compounds by elements");
-					let components = fertilizer_box.components_percentage(&known_elements);
-
-					for displayed_elt in components {
-						println!("{:?}", displayed_elt);
+					info_println!(opts, "Fertilizer: {}", fertilizer_box.name().bold());
+					if let Some(description) = fertilizer_box.description().filter(|descr| !descr.is_empty()) {
+						info_println!(opts, "Description: {}", description);
 					}
+					if let Some(manufacturer) = fertilizer_box.manufacturer() {
+						info_println!(opts, "Manufacturer: {}", manufacturer);
+					}
+					print_components(&**fertilizer_box, &known_elements, &opts);
 					dyn_clone::clone(fertilizer_box)
 				},
 				None => {
 					let compound = compound::Compound::new(input.as_str(), &known_elements)?;
-					println!("Compound: {}", compound.name().bold());
-					println!("Molar mass: {}", compound.molar_mass().to_string().bold());
-					println!("Compounds by elements");
-					let components = compound.components_percentage(&known_elements);
-
-					for displayed_elt in components {
-						println!("{:?}", displayed_elt);
-					}
+					info_println!(opts, "Compound: {}", compound.name().bold());
+					info_println!(opts, "Molar mass: {}", compound.molar_mass().to_string().bold());
+					print_components(&compound, &known_elements, &opts);
 					Box::new(compound)
 				},
 			}
 		},
 		FertilizerType::Compound => {
 			let compound = compound::Compound::new_from_stdin(&known_elements, &mut generic_editor)?;
-			println!("Compound: {}", compound.name().bold());
-			println!("Molar mass: {}", compound.molar_mass().to_string().bold());
-			println!("Compounds by elements");
-			let components = compound.components_percentage(&known_elements);
-
-			for displayed_elt in components {
-				println!("{:?}", displayed_elt);
-			}
+			info_println!(opts, "Compound: {}", compound.name().bold());
+			info_println!(opts, "Molar mass: {}", compound.molar_mass().to_string().bold());
+			print_components(&compound, &known_elements, &opts);
 			Box::new(compound)
 		},
 		FertilizerType::Mix => {
 			let mix = mix::MixedFertilizer::new_from_stdin(&known_elements, &mut fert_editor)?;
-			println!("Mix: {}", mix.name().bold());
-			println!("Compounds by elements");
-			let components = mix.components_percentage(&known_elements);
-
-			for displayed_elt in components {
-				println!("{:?}", displayed_elt);
-			}
+			info_println!(opts, "Mix: {}", mix.name().bold());
+			print_components(&mix, &known_elements, &opts);
 			Box::new(mix)
 		},
 	};
@@ -250,31 +429,106 @@ async fn main() -> Result<()> {
 	let tank = if let Some(tank_toml) = &opts.tank_toml {
 		let data = fs::read_to_string(tank_toml.as_path())?;
 		tank::Tank::new_from_toml(data.as_str())?
-	} else if opts.tank_input == TankInputMode::Linear {
-		tank::Tank::new_from_stdin_linear(opts.absolute, &mut generic_editor)?
+	} else if let Some(tank_yaml) = &opts.tank_yaml {
+		let data = fs::read_to_string(tank_yaml.as_path())?;
+		tank::Tank::new_from_yaml(data.as_str())?
 	} else {
-		tank::Tank::new_from_stdin_volume(opts.absolute, &mut generic_editor)?
+		match opts.tank_input {
+			TankInputMode::Linear => tank::Tank::new_from_stdin_linear(opts.absolute, &mut generic_editor)?,
+			TankInputMode::Volume => tank::Tank::new_from_stdin_volume(opts.absolute, &mut generic_editor)?,
+			TankInputMode::HalfSphere => tank::Tank::new_from_stdin_half_sphere(opts.absolute, &mut generic_editor)?,
+		}
+	};
+	let tank = match opts.water_change {
+		Some(pct) => tank.with_water_change(pct)?,
+		None => tank,
 	};
 
-	println!("{:?}", &tank);
+	info_println!(opts, "{:?}", &tank);
 
-	let dosages =
-		match opts.dosing_method {
-			DosingMethod::Dry =>
-				concentration::DryDosing::new_from_stdin(opts.calc.into(), &known_elements, &mut generic_editor)?
-					.dilute(&*fertilizer, &known_elements, &tank)?,
-			DosingMethod::Solution =>
-				concentration::SolutionDosing::new_from_stdin(opts.calc.into(), &known_elements, &mut generic_editor)?
-					.dilute(&*fertilizer, &known_elements, &tank)?,
-		};
+	loop {
+		calculate_dose(&*fertilizer, &tank, &known_elements, &opts, &mut generic_editor)?;
+
+		// Machine readable outputs are one-shot
+		if opts.output != OutputFormat::Pretty {
+			break
+		}
+
+		match generic_editor.readline("Compute another dose for the same fertilizer and tank? [y/N]: ") {
+			Ok(answer) if answer.trim().eq_ignore_ascii_case("y") => continue,
+			Ok(_) | Err(ReadlineError::Eof) => break,
+			Err(err) => return Err(err.into()),
+		}
+	}
+
+	Ok(())
+}
+
+// Reads dosing parameters and prints the dilution result for the selected fertilizer and tank
+fn calculate_dose(
+	fertilizer: &dyn Fertilizer,
+	tank: &tank::Tank,
+	known_elements: &KnownElements,
+	opts: &Opts,
+	editor: &mut traits::Editor<()>,
+) -> Result<()> {
+	// Stock solution concentration in g/L is displayed for the solution dosing only
+	let (dosages, stock_concentration) = match opts.dosing_method {
+		DosingMethod::Dry => {
+			let dosing = concentration::DryDosing::new_from_stdin(opts.calc.into(), known_elements, editor)?;
+			dosing.validate()?;
+			(dosing.dilute(fertilizer, known_elements, tank)?, None)
+		},
+		DosingMethod::Solution => {
+			let dosing = concentration::SolutionDosing::new_from_stdin(opts.calc.into(), known_elements, editor)?;
+			dosing.validate()?;
+			let dosages = dosing.dilute(fertilizer, known_elements, tank)?;
+			let stock_concentration = dosing.stock_solution_concentration(dosages.compound_dose);
+			(dosages, Some(stock_concentration))
+		},
+	};
+
+	let dosages = match opts.sort_by {
+		SortBy::Priority => dosages,
+		SortBy::Concentration => dosages.sort_by_concentration_desc(),
+	};
+
+	match opts.output {
+		OutputFormat::Plain => {
+			println!("{}", dosages);
+			return Ok(())
+		},
+		OutputFormat::Json => {
+			println!("{}", serde_json::to_string_pretty(&dosages)?);
+			return Ok(())
+		},
+		OutputFormat::Pretty => {},
+	}
+
+	if let (Some(stock_volume), Some(stock_concentration)) = (dosages.stock_volume_ml, stock_concentration) {
+		println!(
+			"Stock solution: {:.3}g in {:.0}mL = {:.2}g/L",
+			dosages.compound_dose, stock_volume, stock_concentration
+		);
+	}
 
-	if opts.calc == CalculationType::Target {
-		println!("You need to add {:.3} grams of fertilizer to reach your target", dosages.compound_dose);
+	match opts.calc {
+		CalculationType::Target =>
+			println!("You need to add {:.3} grams of fertilizer to reach your target", dosages.compound_dose),
+		CalculationType::WeeklyEI => println!(
+			"You need to add {:.3} grams of fertilizer 3 times per week to reach your target",
+			dosages.compound_dose
+		),
+		CalculationType::Dose => {},
 	}
 	println!("Dose by elements");
 
-	for dosage in dosages.elements_dose {
-		println!("{:?}", &dosage);
+	for dosage in dosages
+		.elements_dose
+		.iter()
+		.filter(|elt| is_displayed(&elt.element, opts.element_group.as_deref()))
+	{
+		println!("{:?}", dosage);
 	}
 
 	Ok(())
@@ -11,10 +11,13 @@ use crate::{
 	compound::Compound,
 	concentration::{ElementConcentrationAlias, ElementsConcentrationsWithAliases},
 	elements::{Element, KnownElements},
-	traits::Editor,
+	traits::{Editor, FertilizerKind},
 	Fertilizer,
 };
 
+/// Tolerance for the total elements fraction in a mix
+const MIX_TOTAL_EPSILON: f64 = 1e-6;
+
 /// Represents a pre-mixed set of elements
 #[derive(Default, Clone)]
 pub struct MixedFertilizer {
@@ -22,8 +25,10 @@ pub struct MixedFertilizer {
 	pub elements_composition: HashMap<Element, f64>,
 	/// Public name of the mix
 	pub name: String,
-	/// Description of the fertilizer
-	pub description: String,
+	/// Description of the fertilizer if specified
+	pub description: Option<String>,
+	/// Optional manufacturer of the fertilizer
+	pub manufacturer: Option<String>,
 }
 
 impl Display for MixedFertilizer {
@@ -40,13 +45,21 @@ impl PartialEq for MixedFertilizer {
 
 impl Eq for MixedFertilizer {}
 
-/// Macro elements composition from the fertilizer declaration
+/// Macro elements composition from the fertilizer declaration, optionally with micro elements
 #[derive(Default)]
 pub struct MacroElements {
 	pub nitrogen_percentage: f64,
 	pub p2o5_percentage: f64,
 	pub k2o_percentage: f64,
 	pub mgo_percentage: f64,
+	pub cao_percentage: f64,
+	pub so3_percentage: f64,
+	pub fe_percentage: f64,
+	pub mn_percentage: f64,
+	pub zn_percentage: f64,
+	pub b_percentage: f64,
+	pub cu_percentage: f64,
+	pub mo_percentage: f64,
 }
 
 // Check if all elements are sane for input of the mixed fertilizer
@@ -88,48 +101,65 @@ fn is_sane_elements(known_elements: &KnownElements) -> Result<()> {
 	Ok(())
 }
 
+// Formats a percentage for a trivial name: two decimal digits at most, trailing zeros trimmed
+fn format_npk_percentage(percentage: f64) -> String {
+	let formatted = format!("{:.2}", percentage);
+	formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
 impl MacroElements {
 	// Creates a trivial name from percentage
 	pub fn name_from_npk(&self) -> String {
+		let npk = [self.nitrogen_percentage, self.p2o5_percentage, self.k2o_percentage]
+			.map(format_npk_percentage)
+			.join(":");
+
 		if self.mgo_percentage > f64::EPSILON {
-			format!(
-				"NPK+Mg-{:0}:{:0}:{:0}+{:0}",
-				self.nitrogen_percentage, self.p2o5_percentage, self.k2o_percentage, self.mgo_percentage
-			)
+			format!("NPK+Mg-{}+{}", npk, format_npk_percentage(self.mgo_percentage))
 		} else {
-			format!("NPK-{:0}:{:0}:{:0}", self.nitrogen_percentage, self.p2o5_percentage, self.k2o_percentage)
+			format!("NPK-{}", npk)
 		}
 	}
 }
 
 impl MixedFertilizer {
-	// Push concentrations from macro elements in fetilizer declaration
-	fn push_macro_elements(&mut self, macros: &MacroElements, known_elements: &KnownElements) {
-		if macros.nitrogen_percentage > f64::EPSILON {
-			self.elements_composition
-				.insert(known_elements.elements.get("N").unwrap().clone(), macros.nitrogen_percentage / 100.0);
-		}
-		if macros.p2o5_percentage > f64::EPSILON {
-			let p = known_elements.elements.get("P").unwrap();
-			self.elements_composition.insert(
-				p.clone(),
-				p.element_from_alias_rate("P2O5", known_elements).unwrap() * macros.p2o5_percentage / 100.0,
-			);
-		}
-		if macros.k2o_percentage > f64::EPSILON {
-			let k = known_elements.elements.get("K").unwrap();
-			self.elements_composition.insert(
-				k.clone(),
-				k.element_from_alias_rate("K2O", known_elements).unwrap() * macros.k2o_percentage / 100.0,
-			);
-		}
-		if macros.mgo_percentage > f64::EPSILON {
-			let mg = known_elements.elements.get("Mg").unwrap();
-			self.elements_composition.insert(
-				mg.clone(),
-				mg.element_from_alias_rate("MgO", known_elements).unwrap() * macros.mgo_percentage / 100.0,
-			);
+	// Push concentration of the element declared either as is or as an oxide (or another alias)
+	fn push_element_percentage(
+		&mut self,
+		symbol: &str,
+		alias: Option<&str>,
+		percentage: f64,
+		known_elements: &KnownElements,
+	) -> Result<()> {
+		if percentage > f64::EPSILON {
+			let elt = known_elements
+				.elements
+				.get(symbol)
+				.ok_or_else(|| anyhow!("missing {} in known elements", symbol))?;
+			let rate = match alias {
+				Some(alias) => elt.element_from_alias_rate(alias, known_elements)?,
+				None => 1.0,
+			};
+			self.elements_composition.insert(elt.clone(), rate * percentage / 100.0);
 		}
+
+		Ok(())
+	}
+
+	// Push concentrations from macro elements in fetilizer declaration
+	fn push_macro_elements(&mut self, macros: &MacroElements, known_elements: &KnownElements) -> Result<()> {
+		self.push_element_percentage("N", None, macros.nitrogen_percentage, known_elements)?;
+		self.push_element_percentage("P", Some("P2O5"), macros.p2o5_percentage, known_elements)?;
+		self.push_element_percentage("K", Some("K2O"), macros.k2o_percentage, known_elements)?;
+		self.push_element_percentage("Mg", Some("MgO"), macros.mgo_percentage, known_elements)?;
+		self.push_element_percentage("Ca", Some("CaO"), macros.cao_percentage, known_elements)?;
+		self.push_element_percentage("S", Some("SO3"), macros.so3_percentage, known_elements)?;
+		self.push_element_percentage("Fe", None, macros.fe_percentage, known_elements)?;
+		self.push_element_percentage("Mn", None, macros.mn_percentage, known_elements)?;
+		self.push_element_percentage("Zn", None, macros.zn_percentage, known_elements)?;
+		self.push_element_percentage("B", None, macros.b_percentage, known_elements)?;
+		self.push_element_percentage("Cu", None, macros.cu_percentage, known_elements)?;
+		self.push_element_percentage("Mo", None, macros.mo_percentage, known_elements)
 	}
 	/// Parses a mixed fertilizer from stdin
 	pub fn new_from_stdin<T: Helper>(known_elements: &KnownElements, editor: &mut Editor<T>) -> Result<Self> {
@@ -146,72 +176,88 @@ impl MixedFertilizer {
 		let input: String = editor.readline("Input total MgO in percents: ")?;
 		macros.mgo_percentage = input.parse::<f64>()?;
 
+		// Optional elements, empty input means that an element is absent
+		let mut read_optional = |prompt: &str| -> Result<f64> {
+			let input: String = editor.readline(prompt)?;
+			let input = input.trim();
+			Ok(if input.is_empty() { 0.0 } else { input.parse::<f64>()? })
+		};
+		macros.cao_percentage = read_optional("Input total CaO in percents (empty if none): ")?;
+		macros.so3_percentage = read_optional("Input total SO3 in percents (empty if none): ")?;
+		macros.fe_percentage = read_optional("Input total Fe in percents (empty if none): ")?;
+		macros.mn_percentage = read_optional("Input total Mn in percents (empty if none): ")?;
+		macros.zn_percentage = read_optional("Input total Zn in percents (empty if none): ")?;
+		macros.b_percentage = read_optional("Input total B in percents (empty if none): ")?;
+		macros.cu_percentage = read_optional("Input total Cu in percents (empty if none): ")?;
+		macros.mo_percentage = read_optional("Input total Mo in percents (empty if none): ")?;
+
 		let mut res = Self { name: macros.name_from_npk(), ..Default::default() };
 
-		res.push_macro_elements(&macros, known_elements);
+		res.push_macro_elements(&macros, known_elements)?;
 
 		Ok(res)
 	}
 
-	// Used for tests currently but might be used for something else
-	#[allow(dead_code)]
+	/// Creates a mixed fertilizer from the label values
 	pub fn new_from_npk(macros: &MacroElements, known_elements: &KnownElements) -> Result<Self> {
 		is_sane_elements(known_elements)?;
 
 		let mut res = Self { name: macros.name_from_npk(), ..Default::default() };
-		res.push_macro_elements(macros, known_elements);
+		res.push_macro_elements(macros, known_elements)?;
 
 		Ok(res)
 	}
 
-	/// Parse a mixed fertilizer from a toml object
+	/// Parse a mixed fertilizer from a toml object, `is_percents` is the default for a missing `percents` flag
 	pub fn new_from_toml_object(
 		name: &str,
 		obj: &toml::Value,
 		known_elements: &KnownElements,
 		is_percents: bool,
 	) -> Result<Self> {
-		if !obj.is_table() {
-			return Err(anyhow!("expect input to be a toml object"))
-		}
+		let table = obj.as_table().ok_or_else(|| anyhow!("expect input to be a toml object"))?;
 
-		let compounds = obj
-			.as_table()
-			.unwrap()
+		let compounds = table
 			.get("compounds")
 			.ok_or_else(|| anyhow!("no `compounds` object in a mix"))?
 			.as_table()
 			.ok_or_else(|| anyhow!("expect compounds to be an object"))?;
 
-		let description = if let Some(descr_obj) = obj.as_table().unwrap().get("description") {
-			descr_obj.as_str().unwrap_or("")
-		} else {
-			""
+		let description = table
+			.get("description")
+			.and_then(|descr_obj| descr_obj.as_str())
+			.map(str::to_owned);
+
+		let manufacturer = table
+			.get("manufacturer")
+			.and_then(|manufacturer_obj| manufacturer_obj.as_str())
+			.map(str::to_owned);
+
+		// Per fertilizer override of the compounds portions format
+		let is_percents = match table.get("percents") {
+			Some(percents_obj) => percents_obj
+				.as_bool()
+				.ok_or_else(|| anyhow!("`percents` must be a boolean: {}", percents_obj))?,
+			None => is_percents,
 		};
 
-		let mut res = Self { name: name.to_owned(), description: description.to_owned(), ..Default::default() };
-
-		// Ineffective, but who cares
-		if !compounds
-			.iter()
-			.all(|(k, v)| (v.is_integer() || v.is_float()) && Compound::new(k.as_str(), known_elements).is_ok())
-		{
-			return Err(anyhow!("incorrect compounds definition"))
-		}
+		let mut res = Self { name: name.to_owned(), description, manufacturer, ..Default::default() };
 
 		let compounds_portions = compounds
 			.iter()
-			.filter(|(_, v)| (v.is_integer() || v.is_float()))
-			.map(|(k, v)| {
-				(
-					Compound::new(k.as_str(), known_elements)
-						.unwrap()
-						.components_percentage(known_elements),
-					if is_percents { extract_toml_number(v) / 100.0 } else { extract_toml_number(v) },
-				)
-			});
-
-		compounds_portions.for_each(|(elements_percentages, portion)| {
+			.map(|(k, v)| -> Result<_> {
+				let compound = Compound::new(k.as_str(), known_elements)
+					.map_err(|e| anyhow!("incorrect compounds definition: {}: {}", k, e))?;
+				let portion =
+					extract_toml_number(v).map_err(|e| anyhow!("incorrect compounds definition: {}: {}", k, e))?;
+				Ok((
+					compound.components_percentage(known_elements),
+					if is_percents { portion / 100.0 } else { portion },
+				))
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		compounds_portions.into_iter().for_each(|(elements_percentages, portion)| {
 			elements_percentages
 				.iter()
 				.filter(|e| !e.element.is_insignificant())
@@ -253,16 +299,42 @@ impl Fertilizer for MixedFertilizer {
 		self.name.as_str()
 	}
 
-	fn description(&self) -> String {
+	fn description(&self) -> Option<String> {
 		self.description.clone()
 	}
+
+	fn kind(&self) -> FertilizerKind {
+		FertilizerKind::Mix
+	}
+
+	fn manufacturer(&self) -> Option<&str> {
+		self.manufacturer.as_deref()
+	}
+
+	fn validate(&self, _known_elts: &KnownElements) -> Result<()> {
+		for (element, fraction) in self.elements_composition.iter() {
+			if !fraction.is_finite() || *fraction < 0.0 {
+				return Err(anyhow!("invalid fraction of {} in {}: {}", element.name, self.name, fraction))
+			}
+		}
+
+		let total: f64 = self.elements_composition.values().sum();
+		// Allow some rounding errors for the mixes that are declared as 100% of elements
+		if total > 1.0 + MIX_TOTAL_EPSILON {
+			return Err(anyhow!("total elements fraction in {} exceeds 100%: {:.2}%", self.name, total * 100.0))
+		}
+
+		Ok(())
+	}
 }
 
-fn extract_toml_number(val: &toml::Value) -> f64 {
-	match *val {
-		toml::Value::Float(f) => f,
-		toml::Value::Integer(i) => i as f64,
-		_ => panic!("must not be reached"),
+// Numbers quoted as strings (e.g. `KNO3 = "50"`) are also accepted
+fn extract_toml_number(val: &toml::Value) -> Result<f64> {
+	match val {
+		toml::Value::Float(f) => Ok(*f),
+		toml::Value::Integer(i) => Ok(*i as f64),
+		toml::Value::String(s) => s.trim().parse::<f64>().map_err(|e| anyhow!("invalid number {:?}: {}", s, e)),
+		_ => Err(anyhow!("expected a number, got {}", val.type_str())),
 	}
 }
 
@@ -287,14 +359,38 @@ mod tests {
 		.unwrap();
 		assert_eq!(fert.name(), "NPK-24:8:16");
 		let percentages = fert.components_percentage(&known_elements);
-		assert_eq!(percentages[0].element.name, "N");
+		assert_eq!(percentages[0].element.name.as_ref(), "N");
 		assert_delta_eq!(percentages[0].concentration, 24.0 / 100.0, MOLAR_MASS_EPSILON);
-		assert_eq!(percentages[1].element.name, "P");
+		assert_eq!(percentages[1].element.name.as_ref(), "P");
 		assert_delta_eq!(percentages[1].concentration, 3.5 / 100.0, MOLAR_MASS_EPSILON);
-		assert_eq!(percentages[2].element.name, "K");
+		assert_eq!(percentages[2].element.name.as_ref(), "K");
 		assert_delta_eq!(percentages[2].concentration, 13.3 / 100.0, MOLAR_MASS_EPSILON);
 	}
 
+	#[test]
+	fn name_from_npk_decimals() {
+		let macros = MacroElements {
+			nitrogen_percentage: 12.0,
+			p2o5_percentage: 10.0 / 3.0,
+			k2o_percentage: 0.5,
+			..Default::default()
+		};
+		assert_eq!(macros.name_from_npk(), "NPK-12:3.33:0.5");
+		let macros = MacroElements { mgo_percentage: 2.0, ..macros };
+		assert_eq!(macros.name_from_npk(), "NPK+Mg-12:3.33:0.5+2");
+	}
+
+	#[test]
+	fn format_npk_percentage_boundaries() {
+		assert_eq!(format_npk_percentage(12.96), "12.96");
+		assert_eq!(format_npk_percentage(13.04), "13.04");
+		assert_eq!(format_npk_percentage(13.004), "13");
+		assert_eq!(format_npk_percentage(12.999), "13");
+		assert_eq!(format_npk_percentage(2.5), "2.5");
+		assert_eq!(format_npk_percentage(20.0), "20");
+		assert_eq!(format_npk_percentage(0.0), "0");
+	}
+
 	// Compare fertilizer declaration for chempak tomato fertilizer
 	#[test]
 	fn chempak_tomato() {
@@ -305,19 +401,139 @@ mod tests {
 				p2o5_percentage: 9.0,
 				k2o_percentage: 30.0,
 				mgo_percentage: 2.5,
+				..Default::default()
 			},
 			&known_elements,
 		)
 		.unwrap();
 		assert_eq!(fert.name(), "NPK+Mg-11:9:30+2.5");
 		let percentages = fert.components_percentage(&known_elements);
-		assert_eq!(percentages[0].element.name, "N");
+		assert_eq!(percentages[0].element.name.as_ref(), "N");
 		assert_delta_eq!(percentages[0].concentration, 11.0 / 100.0, MOLAR_MASS_EPSILON);
-		assert_eq!(percentages[1].element.name, "P");
+		assert_eq!(percentages[1].element.name.as_ref(), "P");
 		assert_delta_eq!(percentages[1].concentration, 3.9 / 100.0, MOLAR_MASS_EPSILON);
-		assert_eq!(percentages[2].element.name, "K");
+		assert_eq!(percentages[2].element.name.as_ref(), "K");
 		assert_delta_eq!(percentages[2].concentration, 24.9 / 100.0, MOLAR_MASS_EPSILON);
-		assert_eq!(percentages[3].element.name, "Mg");
+		assert_eq!(percentages[3].element.name.as_ref(), "Mg");
 		assert_delta_eq!(percentages[3].concentration, 1.5 / 100.0, MOLAR_MASS_EPSILON);
 	}
+
+	// Compare fertilizer declaration with micro elements to the database entry
+	#[test]
+	fn solufeed_high_k() {
+		let known_elements = load_known_elements();
+		let fert = MixedFertilizer::new_from_npk(
+			&MacroElements {
+				nitrogen_percentage: 15.0,
+				p2o5_percentage: 6.9,
+				k2o_percentage: 32.0,
+				mgo_percentage: 3.0,
+				fe_percentage: 0.175,
+				mn_percentage: 0.060,
+				zn_percentage: 0.075,
+				b_percentage: 0.021,
+				cu_percentage: 0.010,
+				mo_percentage: 0.005,
+				..Default::default()
+			},
+			&known_elements,
+		)
+		.unwrap();
+		let fertilizers = load_known_fertilizers(&known_elements);
+		let expected = fertilizers.known_fertilizers["Solufeed High K"].components_percentage(&known_elements);
+		let percentages = fert.components_percentage(&known_elements);
+		assert_eq!(percentages.len(), 10);
+		assert_eq!(percentages.len(), expected.len());
+
+		for (elt, expected_elt) in percentages.iter().zip(expected.iter()) {
+			assert_eq!(elt.element, expected_elt.element);
+			assert_delta_eq!(elt.concentration, expected_elt.concentration, MOLAR_MASS_EPSILON);
+		}
+	}
+
+	#[test]
+	fn micro_elements() {
+		let known_elements = load_known_elements();
+		let iron = known_elements.elements.get("Fe").unwrap().clone();
+		let fert = MixedFertilizer {
+			elements_composition: HashMap::from([(iron.clone(), 0.005)]),
+			name: "Iron".to_owned(),
+			..Default::default()
+		};
+		let percentages = fert.components_percentage(&known_elements);
+		assert_eq!(percentages.len(), 1);
+		assert_eq!(percentages[0].element, iron);
+		assert_delta_eq!(percentages[0].concentration, 0.005, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn toml_manufacturer() {
+		let known_elements = load_known_elements();
+		let obj: toml::Value = toml::from_str(
+			r#"
+			description = "Test mix"
+			manufacturer = "Acme"
+			[compounds]
+			N = 24
+			"#,
+		)
+		.unwrap();
+		let fert = MixedFertilizer::new_from_toml_object("Test", &obj, &known_elements, true).unwrap();
+		assert_eq!(fert.manufacturer(), Some("Acme"));
+		let fertilizers = load_known_fertilizers(&known_elements);
+		assert!(fertilizers.known_fertilizers["Miracle Gro"].manufacturer().is_none());
+		assert_eq!(fert.description(), Some("Test mix".to_owned()));
+		assert!(fertilizers.known_fertilizers["Miracle Gro"].description().is_none());
+	}
+
+	#[test]
+	fn toml_string_numbers() {
+		let known_elements = load_known_elements();
+		let numbers: toml::Value = toml::from_str("[compounds]\nKNO3 = 50\n").unwrap();
+		let strings: toml::Value = toml::from_str("[compounds]\nKNO3 = \"50\"\n").unwrap();
+		let numbers = MixedFertilizer::new_from_toml_object("Test", &numbers, &known_elements, true).unwrap();
+		let strings = MixedFertilizer::new_from_toml_object("Test", &strings, &known_elements, true).unwrap();
+		assert_eq!(numbers.elements_composition, strings.elements_composition);
+		for invalid in ["[compounds]\nKNO3 = \"fifty\"\n", "[compounds]\nKNO3 = true\n", "[compounds]\nXyz = 1\n"] {
+			let obj: toml::Value = toml::from_str(invalid).unwrap();
+			let err = MixedFertilizer::new_from_toml_object("Test", &obj, &known_elements, true)
+				.err()
+				.unwrap();
+			assert!(err.to_string().contains("incorrect compounds definition"), "{}", err);
+		}
+	}
+
+	#[test]
+	fn toml_percents_flag() {
+		let known_elements = load_known_elements();
+		let percents: toml::Value = toml::from_str("[compounds]\nN = 24\n").unwrap();
+		let fractions: toml::Value = toml::from_str("percents = false\n[compounds]\nN = 0.24\n").unwrap();
+		let percents = MixedFertilizer::new_from_toml_object("Test", &percents, &known_elements, true).unwrap();
+		let fractions = MixedFertilizer::new_from_toml_object("Test", &fractions, &known_elements, true).unwrap();
+		let n_percents = percents.components_percentage(&known_elements)[0].concentration;
+		let n_fractions = fractions.components_percentage(&known_elements)[0].concentration;
+		assert_delta_eq!(n_percents, 0.24, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(n_fractions, n_percents, MOLAR_MASS_EPSILON);
+		let invalid: toml::Value = toml::from_str("percents = \"yes\"\n[compounds]\nN = 24\n").unwrap();
+		let err = MixedFertilizer::new_from_toml_object("Test", &invalid, &known_elements, true)
+			.err()
+			.unwrap();
+		assert!(err.to_string().contains("`percents` must be a boolean"), "{}", err);
+		let not_table = toml::Value::Integer(42);
+		assert!(MixedFertilizer::new_from_toml_object("Test", &not_table, &known_elements, true).is_err());
+	}
+
+	#[test]
+	fn validate_mix() {
+		let known_elements = load_known_elements();
+		let mut fert = MixedFertilizer::new_from_npk(
+			&MacroElements { nitrogen_percentage: 24.0, ..Default::default() },
+			&known_elements,
+		)
+		.unwrap();
+		assert!(fert.validate(&known_elements).is_ok());
+		fert.elements_composition
+			.insert(known_elements.elements.get("K").unwrap().clone(), 0.9);
+		assert!(fert.validate(&known_elements).is_err());
+	}
 }
@@ -1,6 +1,5 @@
 use crate::traits::Editor;
 use anyhow::{anyhow, Result};
-use either::Either;
 use length::{Length, MetricUnit::*};
 use rustyline::Helper;
 use serde::{Deserialize, Serialize};
@@ -8,8 +7,11 @@ use std::fmt::{Debug, Formatter};
 
 /// More or less real approximation of the volume to real volume relation
 const REAL_VOLUME_MULT: f64 = 0.85;
+/// Liters in one US liquid gallon
+const LITERS_PER_US_GALLON: f64 = 3.785411784;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Rectangular tank, all dimensions are in decimeters
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 struct LinearDimensions {
 	height: f64,
 	length: f64,
@@ -17,21 +19,96 @@ struct LinearDimensions {
 }
 
 impl LinearDimensions {
+	/// All dimensions must be in decimetres; the result is in litres
 	fn volume(&self) -> f64 {
 		self.height * self.length * self.width
 	}
 }
 
+/// Bowl shaped tank, diameter is in centimeters
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct HalfSphereDimensions {
+	diameter: f64,
+}
+
+impl HalfSphereDimensions {
+	fn volume(&self) -> f64 {
+		// Convert to decimeters to get liters
+		let radius = self.diameter / 10.0 / 2.0;
+		2.0 / 3.0 * std::f64::consts::PI * radius.powi(3)
+	}
+}
+
+/// Tank volume either as is (in liters) or as tank dimensions
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum TankVolume {
+	Volume(f64),
+	Linear(LinearDimensions),
+	HalfSphere { half_sphere: HalfSphereDimensions },
+}
+
+// Tank dimensions and volumes are divisors in doses calculations, so they must be positive and finite
+fn check_positive(what: &str, value: f64) -> Result<()> {
+	if value.is_finite() && value > 0.0 {
+		Ok(())
+	} else {
+		Err(anyhow!("tank {} must be a positive number: {}", what, value))
+	}
+}
+
+impl TankVolume {
+	fn validate(&self) -> Result<()> {
+		match self {
+			TankVolume::Volume(volume) => check_positive("volume", *volume),
+			TankVolume::Linear(lin) => {
+				check_positive("height", lin.height)?;
+				check_positive("length", lin.length)?;
+				check_positive("width", lin.width)
+			},
+			TankVolume::HalfSphere { half_sphere } => check_positive("diameter", half_sphere.diameter),
+		}
+	}
+}
+
 /// Tank volume holder
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(try_from = "TankData")]
 pub struct Tank {
-	#[serde(with = "either::serde_untagged")]
-	volume: Either<f64, LinearDimensions>,
+	/// Optional label of the tank, e.g. `Main display`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	volume: TankVolume,
 	#[serde(default)]
 	absolute: bool,
 }
 
+// Unvalidated tank as it is deserialized
+#[derive(Deserialize)]
+struct TankData {
+	#[serde(default)]
+	name: Option<String>,
+	volume: TankVolume,
+	#[serde(default)]
+	absolute: bool,
+}
+
+impl TryFrom<TankData> for Tank {
+	type Error = anyhow::Error;
+
+	fn try_from(data: TankData) -> Result<Self> {
+		Tank { name: data.name, volume: data.volume, absolute: data.absolute }.validated()
+	}
+}
+
 impl Tank {
+	// Returns the tank if its dimensions and the resulting volume are usable
+	fn validated(self) -> Result<Self> {
+		self.volume.validate()?;
+		check_positive("volume", self.metric_volume())?;
+		Ok(self)
+	}
+
 	fn length_from_string_as_dm(s: &str) -> Result<f64> {
 		let s = s.trim();
 		let last_char = s.chars().last().ok_or_else(|| anyhow!("empty dimension"))?;
@@ -46,23 +123,48 @@ impl Tank {
 			Ok(dim.value)
 		}
 	}
+	// Parses volume in liters, US gallons are accepted with `gal` suffix
+	fn volume_from_string_as_liters(s: &str) -> Result<f64> {
+		let s = s.trim();
+		if let Some(gallons) = s.strip_suffix("gal") {
+			Ok(gallons.trim().parse::<f64>()? * LITERS_PER_US_GALLON)
+		} else {
+			Ok(s.parse::<f64>()?)
+		}
+	}
+
+	// Reads a dimension and echoes how it has been interpreted to avoid silent units confusion
+	fn read_length_as_dm<T: Helper>(prompt: &str, editor: &mut Editor<T>) -> Result<f64> {
+		let input: String =
+			editor.readline(format!("{} (e.g. 90cm, input in cm if no unit is given): ", prompt).as_str())?;
+		let dim = Tank::length_from_string_as_dm(input.as_str())?;
+		eprintln!("Interpreted as {:.2} dm = {:.1} cm", dim, dim * 10.0);
+		Ok(dim)
+	}
+
 	/// Interactively fill tank dimensions
 	pub fn new_from_stdin_linear<T: Helper>(absolute: bool, editor: &mut Editor<T>) -> Result<Self> {
-		let input: String = editor.readline("Tank length (e.g. 90cm): ")?;
-		let length = Tank::length_from_string_as_dm(input.as_str())?;
-		let input: String = editor.readline("Tank width (e.g. 90cm): ")?;
-		let width = Tank::length_from_string_as_dm(input.as_str())?;
-		let input: String = editor.readline("Tank height (e.g. 90cm): ")?;
-		let height = Tank::length_from_string_as_dm(input.as_str())?;
+		let length = Tank::read_length_as_dm("Tank length", editor)?;
+		let width = Tank::read_length_as_dm("Tank width", editor)?;
+		let height = Tank::read_length_as_dm("Tank height", editor)?;
+
+		Self { volume: TankVolume::Linear(LinearDimensions { height, length, width }), absolute, name: None }
+			.validated()
+	}
 
-		Ok(Self { volume: Either::Right(LinearDimensions { height, length, width }), absolute })
+	/// Interactively fill a bowl shaped tank diameter
+	pub fn new_from_stdin_half_sphere<T: Helper>(absolute: bool, editor: &mut Editor<T>) -> Result<Self> {
+		let diameter = Tank::read_length_as_dm("Bowl diameter", editor)? * 10.0;
+
+		Self { volume: TankVolume::HalfSphere { half_sphere: HalfSphereDimensions { diameter } }, absolute, name: None }
+			.validated()
 	}
 
 	/// Load tank from
 	pub fn new_from_stdin_volume<T: Helper>(absolute: bool, editor: &mut Editor<T>) -> Result<Self> {
-		let input: String = editor.readline("Tank volume in liters: ")?;
-		let volume = input.parse::<f64>()?;
-		Ok(Self { volume: Either::Left(volume), absolute })
+		let input: String = editor.readline("Tank volume in liters (or US gallons with `gal` suffix, e.g. 55gal): ")?;
+		let volume = Tank::volume_from_string_as_liters(input.as_str())?;
+		Self { volume: TankVolume::Volume(volume), absolute, name: None }.validated()
 	}
 
 	/// Load tank data from toml
@@ -71,6 +173,12 @@ impl Tank {
 		Ok(tank)
 	}
 
+	/// Load tank data from YAML
+	pub fn new_from_yaml(input: &str) -> Result<Self> {
+		let tank: Tank = serde_yaml::from_str(input)?;
+		Ok(tank)
+	}
+
 	/// Load tank data from JSON (might be useful in future)
 	#[allow(dead_code)]
 	pub fn new_from_json(input: &str) -> Result<Self> {
@@ -79,26 +187,41 @@ impl Tank {
 	}
 
 	/// Returns a real volume of the tank (approximately volume * 0.9)
-	pub fn effective_volume(&self) -> usize {
+	pub fn effective_volume(&self) -> f64 {
 		let mult = if self.absolute { 1.0 } else { REAL_VOLUME_MULT };
-		let vol = match self.volume.as_ref() {
-			Either::Left(vol) => *vol,
-			Either::Right(lin) => lin.volume(),
-		};
-		(vol * mult) as usize
+		self.metric_volume() * mult
 	}
 
-	pub fn metric_volume(&self) -> usize {
-		(match self.volume.as_ref() {
-			Either::Left(vol) => *vol,
-			Either::Right(lin) => lin.volume(),
-		}) as usize
+	/// Returns a tank that holds only the new water after a partial water change of `pct` percents
+	pub fn with_water_change(&self, pct: f64) -> Result<Self> {
+		if !(pct > 0.0 && pct <= 100.0) {
+			return Err(anyhow!("water change must be in (0, 100] percents range: {}", pct))
+		}
+
+		Ok(Self {
+			volume: TankVolume::Volume(self.metric_volume() * pct / 100.0),
+			absolute: self.absolute,
+			name: self.name.clone(),
+		})
+	}
+
+	/// Returns a nominal volume of the tank
+	pub fn metric_volume(&self) -> f64 {
+		match &self.volume {
+			TankVolume::Volume(vol) => *vol,
+			TankVolume::Linear(lin) => lin.volume(),
+			TankVolume::HalfSphere { half_sphere } => half_sphere.volume(),
+		}
 	}
 }
 
 impl Debug for Tank {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		write!(f, "Tank: {} liters real, {} liters nominal", self.effective_volume(), self.metric_volume())?;
+		match &self.name {
+			Some(name) => write!(f, "Tank '{}'", name)?,
+			None => write!(f, "Tank")?,
+		}
+		write!(f, ": {:.1} liters real, {:.1} liters nominal", self.effective_volume(), self.metric_volume())?;
 
 		Ok(())
 	}
@@ -107,6 +230,8 @@ impl Debug for Tank {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::{assert_delta_eq, test_utils::*};
+	use proptest::prelude::*;
 
 	fn sample_tank_linear() -> &'static str {
 		r#"
@@ -121,15 +246,89 @@ mod tests {
 		volume = 200
 		"#
 	}
+	fn sample_tank_nano() -> &'static str {
+		r#"
+		volume = 5.5
+		absolute = true
+		"#
+	}
 
 	#[test]
 	fn test_tanks_toml() {
 		let tank = Tank::new_from_toml(sample_tank_linear()).unwrap();
-		assert_eq!(tank.metric_volume(), 225);
-		assert_eq!(tank.effective_volume(), 191);
+		assert_delta_eq!(tank.metric_volume(), 225.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), 191.25, MOLAR_MASS_EPSILON);
+		let tank = Tank::new_from_toml(sample_tank_volume()).unwrap();
+		assert_delta_eq!(tank.metric_volume(), 200.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), 170.0, MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_tanks_half_sphere() {
+		let tank = Tank::new_from_toml("absolute = true\n[volume.half_sphere]\ndiameter = 30\n").unwrap();
+		assert_delta_eq!(tank.metric_volume(), 7.0686, MOLAR_MASS_EPSILON);
+		let json_tank = Tank::new_from_json(r#"{"volume": {"half_sphere": {"diameter": 30}}}"#).unwrap();
+		assert_delta_eq!(json_tank.metric_volume(), tank.metric_volume(), MOLAR_MASS_EPSILON);
+	}
+
+	#[test]
+	fn test_length_unitless() {
+		assert_delta_eq!(Tank::length_from_string_as_dm("90").unwrap(), 9.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(Tank::length_from_string_as_dm(" 45.5 ").unwrap(), 4.55, MOLAR_MASS_EPSILON);
+		assert!(Tank::length_from_string_as_dm("").is_err());
+	}
+
+	#[test]
+	fn test_volume_gallons() {
+		assert_delta_eq!(Tank::volume_from_string_as_liters("55gal").unwrap(), 208.198, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(Tank::volume_from_string_as_liters("10 gal").unwrap(), 37.854, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(Tank::volume_from_string_as_liters("170").unwrap(), 170.0, MOLAR_MASS_EPSILON);
+		assert!(Tank::volume_from_string_as_liters("gal").is_err());
+	}
+
+	#[test]
+	fn test_tanks_nano() {
+		let tank = Tank::new_from_toml(sample_tank_nano()).unwrap();
+		assert_delta_eq!(tank.effective_volume(), 5.5, MOLAR_MASS_EPSILON);
+		let tank = Tank { absolute: false, ..tank };
+		// Must not be truncated to 4 liters
+		assert_delta_eq!(tank.effective_volume(), 4.675, MOLAR_MASS_EPSILON);
+		assert_eq!(format!("{:?}", tank), "Tank: 4.7 liters real, 5.5 liters nominal");
+		let tank = Tank::new_from_toml(format!("name = \"Shrimp cube\"\n{}", sample_tank_nano()).as_str()).unwrap();
+		assert_eq!(tank.name.as_deref(), Some("Shrimp cube"));
+		assert_eq!(format!("{:?}", tank), "Tank 'Shrimp cube': 5.5 liters real, 5.5 liters nominal");
+		assert_eq!(tank.with_water_change(50.0).unwrap().name.as_deref(), Some("Shrimp cube"));
+		check_roundtrip(&tank).unwrap();
+	}
+
+	#[test]
+	fn test_tanks_water_change() {
 		let tank = Tank::new_from_toml(sample_tank_volume()).unwrap();
-		assert_eq!(tank.metric_volume(), 200);
-		assert_eq!(tank.effective_volume(), 170);
+		let changed = tank.with_water_change(30.0).unwrap();
+		assert_delta_eq!(changed.metric_volume(), 60.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(changed.effective_volume(), 51.0, MOLAR_MASS_EPSILON);
+		assert!(tank.with_water_change(0.0).is_err());
+		assert!(tank.with_water_change(120.0).is_err());
+	}
+
+	#[test]
+	fn test_tanks_invalid_dimensions() {
+		for dimensions in [
+			r#"{"height": 0, "length": 1, "width": 1}"#,
+			r#"{"height": 1, "length": -5, "width": 1}"#,
+			r#"{"height": 1, "length": 1, "width": -0.5}"#,
+			r#"{"height": 1e200, "length": 1e200, "width": 1e200}"#,
+		] {
+			let json = format!(r#"{{"volume": {}}}"#, dimensions);
+			assert!(Tank::new_from_json(json.as_str()).is_err(), "{}", json);
+		}
+		assert!(Tank::new_from_json(r#"{"volume": 0}"#).is_err());
+		assert!(Tank::new_from_json(r#"{"volume": -100}"#).is_err());
+		assert!(Tank::new_from_json(r#"{"volume": {"half_sphere": {"diameter": 0}}}"#).is_err());
+		assert!(Tank::new_from_toml("[volume]\nheight = 0.0\nwidth = 5.0\nlength = 9.0\n").is_err());
+		assert!(Tank::new_from_yaml("volume: { height: 5, width: -5, length: 9 }\n").is_err());
+		let err = Tank::new_from_json(r#"{"volume": {"height": 0, "length": 1, "width": 1}}"#).unwrap_err();
+		assert!(err.to_string().contains("tank height must be a positive number"), "{}", err);
 	}
 
 	fn sample_tank_linear_json() -> &'static str {
@@ -151,10 +350,68 @@ mod tests {
 	#[test]
 	fn test_tanks_json() {
 		let tank = Tank::new_from_json(sample_tank_linear_json()).unwrap();
-		assert_eq!(tank.metric_volume(), 225);
-		assert_eq!(tank.effective_volume(), 191);
+		assert_delta_eq!(tank.metric_volume(), 225.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), 191.25, MOLAR_MASS_EPSILON);
 		let tank = Tank::new_from_json(sample_tank_volume_json()).unwrap();
-		assert_eq!(tank.metric_volume(), 200);
-		assert_eq!(tank.effective_volume(), 170);
+		assert_delta_eq!(tank.metric_volume(), 200.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), 170.0, MOLAR_MASS_EPSILON);
+	}
+
+	fn sample_tank_linear_yaml() -> &'static str {
+		r#"
+volume: { height: 5, width: 5, length: 9 }
+"#
+	}
+	fn sample_tank_volume_yaml() -> &'static str {
+		r#"
+volume: 200
+"#
+	}
+
+	#[test]
+	fn test_tanks_yaml() {
+		let tank = Tank::new_from_yaml(sample_tank_linear_yaml()).unwrap();
+		let toml_tank = Tank::new_from_toml(sample_tank_linear()).unwrap();
+		assert_delta_eq!(tank.metric_volume(), 225.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), toml_tank.effective_volume(), MOLAR_MASS_EPSILON);
+		let tank = Tank::new_from_yaml(sample_tank_volume_yaml()).unwrap();
+		let toml_tank = Tank::new_from_toml(sample_tank_volume()).unwrap();
+		assert_delta_eq!(tank.metric_volume(), 200.0, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(tank.effective_volume(), toml_tank.effective_volume(), MOLAR_MASS_EPSILON);
+	}
+
+	fn check_roundtrip(tank: &Tank) -> Result<(), TestCaseError> {
+		let toml_tank = Tank::new_from_toml(toml::to_string(tank).unwrap().as_str()).unwrap();
+		prop_assert!((toml_tank.effective_volume() - tank.effective_volume()).abs() < MOLAR_MASS_EPSILON);
+		prop_assert!((toml_tank.metric_volume() - tank.metric_volume()).abs() < MOLAR_MASS_EPSILON);
+		prop_assert_eq!(&toml_tank.name, &tank.name);
+		let json_tank = Tank::new_from_json(serde_json::to_string(tank).unwrap().as_str()).unwrap();
+		prop_assert!((json_tank.effective_volume() - tank.effective_volume()).abs() < MOLAR_MASS_EPSILON);
+		prop_assert!((json_tank.metric_volume() - tank.metric_volume()).abs() < MOLAR_MASS_EPSILON);
+		Ok(())
+	}
+
+	proptest! {
+		#[test]
+		fn test_tanks_roundtrip_volume(volume in 0.1f64..10000.0, absolute: bool) {
+			check_roundtrip(&Tank { volume: TankVolume::Volume(volume), absolute, name: None })?;
+		}
+
+		#[test]
+		fn test_tanks_roundtrip_linear(
+			height in 0.1f64..50.0,
+			length in 0.1f64..50.0,
+			width in 0.1f64..50.0,
+			absolute: bool,
+		) {
+			let volume = TankVolume::Linear(LinearDimensions { height, length, width });
+			check_roundtrip(&Tank { volume, absolute, name: None })?;
+		}
+
+		#[test]
+		fn test_tanks_roundtrip_half_sphere(diameter in 1.0f64..200.0, absolute: bool) {
+			let half_sphere = HalfSphereDimensions { diameter };
+			check_roundtrip(&Tank { volume: TankVolume::HalfSphere { half_sphere }, absolute, name: None })?;
+		}
 	}
 }
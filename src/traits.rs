@@ -1,4 +1,5 @@
 use crate::{
+	compound::Compound,
 	concentration::{DiluteCalcType, DiluteResult, ElementsConcentrationsWithAliases},
 	elements::KnownElements,
 	tank::Tank,
@@ -6,12 +7,41 @@ use crate::{
 use anyhow::Result;
 use dyn_clone::DynClone;
 use rustyline::Helper;
+use serde::{Deserialize, Serialize};
+
+/// Kind of the fertilizer definition
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FertilizerKind {
+	/// A single chemical compound, e.g. KNO3
+	Compound,
+	/// A mix of compounds or a fertilizer defined by its label
+	Mix,
+}
 
 /// A generic representation of the fertilizer, must return components percentage for the fertilizer
 pub trait Fertilizer: DynClone {
 	fn components_percentage(&self, known_elts: &KnownElements) -> Vec<ElementsConcentrationsWithAliases>;
+	/// Same as `components_percentage` but also includes insignificant elements (e.g. H or O)
+	fn components_percentage_all(&self, known_elts: &KnownElements) -> Vec<ElementsConcentrationsWithAliases> {
+		self.components_percentage(known_elts)
+	}
 	fn name(&self) -> &str;
-	fn description(&self) -> String;
+	/// Description of the fertilizer, `None` if it is not specified
+	fn description(&self) -> Option<String>;
+	/// Whether the fertilizer is a single compound or a mix
+	fn kind(&self) -> FertilizerKind;
+	/// The compound itself if the fertilizer is a single compound
+	fn as_compound(&self) -> Option<&Compound> {
+		None
+	}
+	/// Manufacturer of the fertilizer if known
+	fn manufacturer(&self) -> Option<&str> {
+		None
+	}
+	/// Checks that the fertilizer composition makes sense
+	fn validate(&self, _known_elts: &KnownElements) -> Result<()> {
+		Ok(())
+	}
 }
 
 pub type Editor<T> = rustyline::Editor<T, rustyline::history::DefaultHistory>;
@@ -33,7 +63,10 @@ pub trait DiluteMethod {
 	fn new_from_json(json: &str) -> Result<Self>
 	where
 		Self: Sized;
+	/// Check dilute method parameters sanity without performing the actual dilution
+	fn validate(&self) -> Result<()>;
 	/// Dilute fertilizer in a specific tank using known dilute method
+	#[must_use = "call .unwrap() or handle the error"]
 	fn dilute(&self, fertilizer: &dyn Fertilizer, known_elements: &KnownElements, tank: &Tank) -> Result<DiluteResult>;
 }
 
@@ -0,0 +1,97 @@
+//! Conversion of the dilution results between concentration units
+
+use crate::{
+	compound::Compound,
+	concentration::{DiluteResult, ElementAliasDose, ElementsDosesWithAliases},
+	elements::KnownElements,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+
+/// Units of the elements concentration in a tank
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, EnumString, Default, PartialEq, Eq)]
+pub enum ConcentrationUnit {
+	/// Milligrams per liter, the native unit of the calculations
+	#[default]
+	MgPerLiter,
+	/// Parts per million, the same as mg/L for water
+	Ppm,
+	/// Millimoles per liter
+	MmolPerLiter,
+}
+
+impl ConcentrationUnit {
+	// Multiplier to convert a value in mg/L to this unit for a substance with the specific molar mass
+	fn rate_from_mg_per_liter(self, molar_mass: f64) -> f64 {
+		match self {
+			ConcentrationUnit::MgPerLiter | ConcentrationUnit::Ppm => 1.0,
+			ConcentrationUnit::MmolPerLiter => 1.0 / molar_mass,
+		}
+	}
+
+	// Multiplier to convert a value from `self` units to `to` units
+	fn rate_to(self, to: ConcentrationUnit, molar_mass: f64) -> f64 {
+		to.rate_from_mg_per_liter(molar_mass) / self.rate_from_mg_per_liter(molar_mass)
+	}
+}
+
+/// Converts elements and aliases doses between concentration units, the compound dose is left intact
+pub fn convert_dose(
+	result: &DiluteResult,
+	from: ConcentrationUnit,
+	to: ConcentrationUnit,
+	known_elements: &KnownElements,
+) -> Result<DiluteResult> {
+	let elements_dose = result
+		.elements_dose
+		.iter()
+		.map(|elt| -> Result<ElementsDosesWithAliases> {
+			let aliases = elt
+				.aliases
+				.iter()
+				.map(|alias| -> Result<ElementAliasDose> {
+					let molar_mass = Compound::new(alias.element_alias.as_str(), known_elements)?.molar_mass();
+					Ok(ElementAliasDose {
+						element_alias: alias.element_alias.clone(),
+						dose: alias.dose * from.rate_to(to, molar_mass),
+					})
+				})
+				.collect::<Result<Vec<_>>>()?;
+
+			Ok(ElementsDosesWithAliases {
+				element: elt.element.clone(),
+				dose: elt.dose * from.rate_to(to, elt.element.molar_mass),
+				aliases,
+			})
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(DiluteResult { elements_dose, ..result.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assert_delta_eq, concentration::DryDosing, test_utils::*, traits::DiluteMethod};
+
+	#[test]
+	fn convert_mmol() {
+		let tank = sample_tank();
+		let known_elts = load_known_elements();
+		let compound = Compound::new("KNO3", &known_elts).unwrap();
+		let dosing = DryDosing::with_dose(1.0);
+		let result = dosing.dilute(&compound, &known_elts, &tank).unwrap();
+		let converted =
+			convert_dose(&result, ConcentrationUnit::MgPerLiter, ConcentrationUnit::MmolPerLiter, &known_elts).unwrap();
+		assert_delta_eq!(converted.compound_dose, result.compound_dose, MOLAR_MASS_EPSILON);
+		// 1 g of KNO3 in 170 L is ~0.0582 mmol/L of every ion
+		assert_delta_eq!(converted.element_dose_by_name("N").unwrap(), 0.0582, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(converted.element_dose_by_name("K").unwrap(), 0.0582, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(converted.alias_dose_by_name("NO3").unwrap(), 0.0582, MOLAR_MASS_EPSILON);
+
+		let back =
+			convert_dose(&converted, ConcentrationUnit::MmolPerLiter, ConcentrationUnit::Ppm, &known_elts).unwrap();
+		assert_delta_eq!(back.element_dose_by_name("N").unwrap(), 0.815, MOLAR_MASS_EPSILON);
+	}
+}
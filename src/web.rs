@@ -1,24 +1,52 @@
 //! A simple web interface
 
-use crate::{compound, concentration::*, elements::KnownElements, tank::Tank, DiluteMethod, Fertilizer, FertilizersDb};
+use crate::{
+	compound,
+	concentration::*,
+	elements::KnownElements,
+	fertilizers_db::DbMetadata,
+	mix::{MacroElements, MixedFertilizer},
+	tank::Tank,
+	traits::FertilizerKind,
+	units::{convert_dose, ConcentrationUnit},
+	DiluteMethod, Fertilizer, FertilizersDb,
+};
 use actix_web::{
+	dev::Server,
+	error::{InternalError, JsonPayloadError},
 	get,
 	http::{header::ContentType, StatusCode},
+	middleware::Logger,
 	post, web, App, HttpResponse, HttpServer, Responder, Result,
 };
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use std::{
+	collections::BTreeMap,
 	fmt,
-	net::ToSocketAddrs,
-	sync::{Arc, Mutex},
+	net::{SocketAddr, ToSocketAddrs},
+	sync::{Arc, Mutex, RwLock},
 };
 use strum::EnumString;
 
+/// Default limit of a JSON request body in bytes
+pub const DEFAULT_MAX_BODY_SIZE: usize = 65536;
+/// Name of the standard access log format of actix-web
+pub const DEFAULT_LOG_FORMAT: &str = "default";
+
 #[derive(Clone)]
 struct WebState {
 	db: Arc<Mutex<FertilizersDb>>,
-	known_elements: Arc<Mutex<KnownElements>>,
+	known_elements: Arc<RwLock<KnownElements>>,
+}
+
+// A fertilizer entry returned by `/list`
+#[derive(Serialize, Deserialize)]
+struct FertilizerSummary {
+	name: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	description: Option<String>,
+	kind: FertilizerKind,
 }
 
 #[get("/list")]
@@ -28,17 +56,22 @@ async fn list_db(state: web::Data<WebState>) -> impl Responder {
 		&locked_db
 			.known_fertilizers
 			.iter()
-			.map(|(name, fert)| (name, fert.description()))
+			.map(|(name, fert)| FertilizerSummary {
+				name: name.clone(),
+				description: fert.description(),
+				kind: fert.kind(),
+			})
 			.collect::<Vec<_>>(),
 	)
 	.unwrap();
 	HttpResponse::Ok().content_type(ContentType::json()).body(body)
 }
 
+/// Deprecated: use `/v2/info/{name}` that also returns the fertilizer name and description
 #[get("/info/{name}")]
 async fn fertilizer_info(name: web::Path<String>, state: web::Data<WebState>) -> impl Responder {
 	let locked_db = state.db.lock().unwrap();
-	let locked_elts = state.known_elements.lock().unwrap();
+	let locked_elts = state.known_elements.read().unwrap();
 	if let Some(fertilizer_box) = locked_db.known_fertilizers.get(name.as_str()) {
 		let components = fertilizer_box.components_percentage(&locked_elts);
 		let body = serde_json::to_string(&components).unwrap();
@@ -57,7 +90,129 @@ async fn fertilizer_info(name: web::Path<String>, state: web::Data<WebState>) ->
 	}
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumString, Deserialize)]
+/// Flat representation of the element concentration: `{ "symbol": "N", "concentration": 0.13, "aliases": { "NO3": 0.61 } }`
+#[derive(Serialize, Deserialize)]
+struct FlatElementConcentration {
+	symbol: String,
+	concentration: f64,
+	aliases: BTreeMap<String, f64>,
+}
+
+impl From<&ElementsConcentrationsWithAliases> for FlatElementConcentration {
+	fn from(elt: &ElementsConcentrationsWithAliases) -> Self {
+		FlatElementConcentration {
+			symbol: elt.element.name.to_string(),
+			concentration: elt.concentration,
+			aliases: elt
+				.aliases
+				.iter()
+				.map(|alias| (alias.element_alias.clone(), alias.concentration))
+				.collect(),
+		}
+	}
+}
+
+// Information about the fertilizer returned by `/v2/info/{name}`
+#[derive(Serialize, Deserialize)]
+struct FertilizerInfoResponse {
+	name: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	description: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	manufacturer: Option<String>,
+	components: Vec<FlatElementConcentration>,
+}
+
+#[get("/v2/info/{name}")]
+async fn fertilizer_info_v2(name: web::Path<String>, state: web::Data<WebState>) -> impl Responder {
+	let locked_db = state.db.lock().unwrap();
+	let locked_elts = state.known_elements.read().unwrap();
+
+	match resolve_fertilizer(name.as_str(), &locked_db, &locked_elts) {
+		Ok(fertilizer) => {
+			let components = fertilizer
+				.components_percentage(&locked_elts)
+				.iter()
+				.map(FlatElementConcentration::from)
+				.collect::<Vec<_>>();
+			let info = FertilizerInfoResponse {
+				name: fertilizer.name().to_owned(),
+				description: fertilizer.description(),
+				manufacturer: fertilizer.manufacturer().map(str::to_owned),
+				components,
+			};
+			let body = serde_json::to_string(&info).unwrap();
+			HttpResponse::Ok().content_type(ContentType::json()).body(body)
+		},
+		Err(_) => HttpResponse::new(StatusCode::NOT_FOUND),
+	}
+}
+
+#[derive(Deserialize)]
+struct BestSourceQuery {
+	element: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BestSource {
+	name: String,
+	concentration: f64,
+}
+
+#[get("/best_source")]
+async fn best_source(query: web::Query<BestSourceQuery>, state: web::Data<WebState>) -> impl Responder {
+	let locked_db = state.db.lock().unwrap();
+	let locked_elts = state.known_elements.read().unwrap();
+
+	match locked_db.find_best_source_for_element(query.element.as_str(), &locked_elts) {
+		Some((name, concentration)) => {
+			let body = serde_json::to_string(&BestSource { name: name.to_owned(), concentration }).unwrap();
+			HttpResponse::Ok().content_type(ContentType::json()).body(body)
+		},
+		None => HttpResponse::new(StatusCode::NOT_FOUND),
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionInfo {
+	version: String,
+	database: Option<DbMetadata>,
+}
+
+#[get("/version")]
+async fn version(state: web::Data<WebState>) -> impl Responder {
+	let locked_db = state.db.lock().unwrap();
+	web::Json(VersionInfo { version: env!("CARGO_PKG_VERSION").to_owned(), database: locked_db.metadata().cloned() })
+}
+
+// Label values of a mixed fertilizer in percents
+#[derive(Deserialize)]
+struct MixQuery {
+	#[serde(default)]
+	n: f64,
+	#[serde(default)]
+	p2o5: f64,
+	#[serde(default)]
+	k2o: f64,
+	#[serde(default)]
+	mgo: f64,
+}
+
+#[get("/mix")]
+async fn mix_info(query: web::Query<MixQuery>, state: web::Data<WebState>) -> Result<impl Responder> {
+	let locked_elts = state.known_elements.read().unwrap();
+	let macros = MacroElements {
+		nitrogen_percentage: query.n,
+		p2o5_percentage: query.p2o5,
+		k2o_percentage: query.k2o,
+		mgo_percentage: query.mgo,
+		..Default::default()
+	};
+	let mix = MixedFertilizer::new_from_npk(&macros, &locked_elts).map_err(|e| -> WebError { e.into() })?;
+	Ok(web::Json(mix.components_percentage(&locked_elts)))
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumString, Deserialize, Serialize)]
 enum DosingMethod {
 	Dry,
 	Solution,
@@ -89,70 +244,177 @@ impl From<serde_json::Error> for WebError {
 	}
 }
 
+// Dosing parameters, the dosing method is explicitly specified by the `type` field
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
-enum WebDosingInput {
+enum DosingData {
 	Dry(DryDosing),
 	Solution(SolutionDosing),
 }
 
-// Generic calculation request for a specific tank and compound/ready fertilizer
+/// Generic calculation request for a specific tank and compound/ready fertilizer
 #[derive(Deserialize, Serialize)]
-struct CalcData {
+pub struct CalcData {
 	tank: Tank,
 	fertilizer: String,
-	dosing_data: WebDosingInput,
+	dosing_data: DosingData,
+}
+
+impl CalcData {
+	/// Creates a request for the dry dosing of the specific fertilizer
+	pub fn from_dry(tank: Tank, fertilizer: &str, dosing: DryDosing) -> CalcData {
+		CalcData { tank, fertilizer: fertilizer.to_owned(), dosing_data: DosingData::Dry(dosing) }
+	}
+
+	/// Creates a request for the solution dosing of the specific fertilizer
+	pub fn from_solution(tank: Tank, fertilizer: &str, dosing: SolutionDosing) -> CalcData {
+		CalcData { tank, fertilizer: fertilizer.to_owned(), dosing_data: DosingData::Solution(dosing) }
+	}
+}
+
+// Finds a fertilizer in the database (by name or by formula) or parses it as a compound
+fn resolve_fertilizer(
+	name: &str,
+	db: &FertilizersDb,
+	known_elements: &KnownElements,
+) -> anyhow::Result<Box<dyn Fertilizer + Send>> {
+	let maybe_known_fertilizer = db.known_fertilizers.get(name).or_else(|| {
+		db.get_by_formula(name, known_elements)
+			.and_then(|(db_name, _)| db.known_fertilizers.get(db_name))
+	});
+
+	match maybe_known_fertilizer {
+		Some(fertilizer_box) => Ok(dyn_clone::clone(fertilizer_box)),
+		None => Ok(Box::new(compound::Compound::new(name, known_elements)?)),
+	}
 }
 
 #[post("/calc")]
 async fn calc(data: web::Json<CalcData>, state: web::Data<WebState>) -> Result<impl Responder> {
 	let locked_db = state.db.lock().unwrap();
-	let maybe_known_fertilizer = locked_db.known_fertilizers.get(data.fertilizer.as_str());
-	let locked_elts = state.known_elements.lock().unwrap();
-
-	let real_ferilizer = match maybe_known_fertilizer {
-		Some(fertilizer_box) => dyn_clone::clone(fertilizer_box),
-		None => {
-			let compound = compound::Compound::new(data.fertilizer.as_str(), &locked_elts)
-				.map_err(|e| -> WebError { e.into() })?;
-			Box::new(compound)
-		},
-	};
+	let locked_elts = state.known_elements.read().unwrap();
+
+	let real_ferilizer =
+		resolve_fertilizer(data.fertilizer.as_str(), &locked_db, &locked_elts).map_err(|e| -> WebError { e.into() })?;
 	let tank = &data.tank;
 	let dosages = match &data.dosing_data {
-		WebDosingInput::Dry(dry_dosing) => dry_dosing
-			.dilute(&*real_ferilizer, &locked_elts, tank)
-			.map_err(|e| -> WebError { e.into() })?,
-		WebDosingInput::Solution(solution_dosing) => solution_dosing
-			.dilute(&*real_ferilizer, &locked_elts, tank)
-			.map_err(|e| -> WebError { e.into() })?,
+		DosingData::Dry(dry_dosing) => {
+			dry_dosing.validate().map_err(|e| -> WebError { e.into() })?;
+			dry_dosing
+				.dilute(&*real_ferilizer, &locked_elts, tank)
+				.map_err(|e| -> WebError { e.into() })?
+		},
+		DosingData::Solution(solution_dosing) => {
+			solution_dosing.validate().map_err(|e| -> WebError { e.into() })?;
+			solution_dosing
+				.dilute(&*real_ferilizer, &locked_elts, tank)
+				.map_err(|e| -> WebError { e.into() })?
+		},
 	};
 	Ok(web::Json(dosages))
 }
 
-pub async fn run_server(
+// Combined dry dosing of several fertilizers to the same tank
+#[post("/batch_dose")]
+async fn batch_dose(data: web::Json<Vec<CalcData>>, state: web::Data<WebState>) -> Result<impl Responder> {
+	let locked_db = state.db.lock().unwrap();
+	let locked_elts = state.known_elements.read().unwrap();
+	let tank = &data.first().ok_or_else(|| -> WebError { anyhow!("empty batch").into() })?.tank;
+
+	let entries = data
+		.iter()
+		.map(|entry| -> anyhow::Result<(Box<dyn Fertilizer>, DryDosing)> {
+			// Equal effective volumes do not mean the same tank, so the whole definitions are compared
+			if entry.tank != *tank {
+				return Err(anyhow!("all batch entries must use the same tank"))
+			}
+
+			let fertilizer = resolve_fertilizer(entry.fertilizer.as_str(), &locked_db, &locked_elts)?;
+
+			match &entry.dosing_data {
+				DosingData::Dry(dry_dosing) => {
+					dry_dosing.validate()?;
+					Ok((fertilizer, dry_dosing.clone()))
+				},
+				DosingData::Solution(_) => Err(anyhow!("batch dosing supports dry dosing only")),
+			}
+		})
+		.collect::<anyhow::Result<Vec<_>>>()
+		.map_err(|e| -> WebError { e.into() })?;
+	let dosages = BatchDosing { entries }
+		.dilute_all(&locked_elts, tank)
+		.map_err(|e| -> WebError { e.into() })?;
+	Ok(web::Json(dosages))
+}
+
+// Dilution result to be converted between concentration units
+#[derive(Deserialize, Serialize)]
+struct ConvertUnitsData {
+	result: DiluteResult,
+	from: ConcentrationUnit,
+	to: ConcentrationUnit,
+}
+
+#[post("/convert_units")]
+async fn convert_units(data: web::Json<ConvertUnitsData>, state: web::Data<WebState>) -> Result<impl Responder> {
+	let locked_elts = state.known_elements.read().unwrap();
+	let converted =
+		convert_dose(&data.result, data.from, data.to, &locked_elts).map_err(|e| -> WebError { e.into() })?;
+	Ok(web::Json(converted))
+}
+
+// JSON extractor configuration, too large bodies are rejected with 413 and a JSON error
+fn json_config(max_body_size: usize) -> web::JsonConfig {
+	web::JsonConfig::default()
+		.limit(max_body_size)
+		.error_handler(|err, _req| match err {
+			JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+				let body = serde_json::json!({ "error": err.to_string() });
+				InternalError::from_response(err, HttpResponse::PayloadTooLarge().json(body)).into()
+			},
+			_ => err.into(),
+		})
+}
+
+/// Binds the web server and returns it together with the actually bound addresses,
+/// so a port 0 can be used to let the OS choose one; the server must be awaited to run
+pub fn run_server(
 	db: Arc<Mutex<FertilizersDb>>,
-	known_elements: Arc<Mutex<KnownElements>>,
+	known_elements: Arc<RwLock<KnownElements>>,
 	listen_addr: impl ToSocketAddrs,
 	static_dir: Option<String>,
-) -> std::io::Result<()> {
+	max_body_size: usize,
+	log_format: String,
+) -> std::io::Result<(Server, Vec<SocketAddr>)> {
 	let state = WebState { db: db.clone(), known_elements: known_elements.clone() };
 
-	HttpServer::new(move || {
+	let server = HttpServer::new(move || {
+		// Access log is written via the `log` crate at the info level
+		let logger =
+			if log_format == DEFAULT_LOG_FORMAT { Logger::default() } else { Logger::new(log_format.as_str()) };
 		let app = App::new()
+			.wrap(logger)
 			.app_data(web::Data::new(state.clone()))
+			.app_data(json_config(max_body_size))
 			.service(list_db)
 			.service(calc)
-			.service(fertilizer_info);
+			.service(batch_dose)
+			.service(convert_units)
+			.service(fertilizer_info)
+			.service(fertilizer_info_v2)
+			.service(best_source)
+			.service(mix_info)
+			.service(version);
 		if let Some(dir) = &static_dir {
 			app.service(actix_files::Files::new("/", dir.as_str()).index_file("index.html"))
 		} else {
 			app
 		}
 	})
-	.bind(listen_addr)?
-	.run()
-	.await
+	.bind(listen_addr)?;
+	let addrs = server.addrs();
+
+	Ok((server.run(), addrs))
 }
 
 #[cfg(test)]
@@ -165,32 +427,18 @@ mod tests {
 		let known_elts = load_known_elements();
 		let known_fertilizers = load_known_fertilizers(&known_elts);
 
-		WebState { db: Arc::new(Mutex::new(known_fertilizers)), known_elements: Arc::new(Mutex::new(known_elts)) }
+		WebState { db: Arc::new(Mutex::new(known_fertilizers)), known_elements: Arc::new(RwLock::new(known_elts)) }
 	}
 
 	fn new_calc_data_dry() -> CalcData {
-		CalcData {
-			fertilizer: "KNO3".to_owned(),
-			tank: sample_tank(),
-			dosing_data: WebDosingInput::Dry(DryDosing {
-				dilute_input: 10.0,
-				target_element: Some("NO3".to_owned()),
-				what: DiluteCalcType::TargetDose,
-			}),
-		}
+		CalcData::from_dry(sample_tank(), "KNO3", DryDosing::with_target("NO3", 10.0))
 	}
 	fn new_calc_data_solution() -> CalcData {
-		CalcData {
-			fertilizer: "KNO3".to_owned(),
-			tank: sample_tank(),
-			dosing_data: WebDosingInput::Solution(SolutionDosing {
-				portion_volume: 20.0,
-				container_volume: 1000.0,
-				solution_input: 10.0,
-				target_element: Some("NO3".to_owned()),
-				what: DiluteCalcType::TargetDose,
-			}),
-		}
+		CalcData::from_solution(
+			sample_tank(),
+			"KNO3",
+			SolutionDosing::with_target("NO3", 10.0).with_container(1000.0, 20.0),
+		)
 	}
 
 	#[actix_web::test]
@@ -198,16 +446,20 @@ mod tests {
 		let app_state = new_state();
 		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(list_db)).await;
 		let req = test::TestRequest::get().uri("/list").to_request();
-		let resp: Vec<(String, String)> = test::call_and_read_body_json(&app, req).await;
+		let resp: Vec<FertilizerSummary> = test::call_and_read_body_json(&app, req).await;
 		assert!(!resp.is_empty());
-		assert!(resp.iter().any(|f| f.0.as_str() == "Urea"));
+		let urea = resp.iter().find(|f| f.name.as_str() == "Urea").unwrap();
+		assert_eq!(urea.kind, FertilizerKind::Compound);
+		let miracle_gro = resp.iter().find(|f| f.name.as_str() == "Miracle Gro").unwrap();
+		assert_eq!(miracle_gro.kind, FertilizerKind::Mix);
+		assert!(resp.windows(2).all(|pair| pair[0].name < pair[1].name));
 	}
 
 	#[actix_web::test]
 	async fn test_info() {
 		let app_state = new_state();
 		let nitrogen = {
-			let locked_elts = app_state.known_elements.lock().unwrap();
+			let locked_elts = app_state.known_elements.read().unwrap();
 			locked_elts.elements.get("N").unwrap().clone()
 		};
 		let app =
@@ -222,6 +474,114 @@ mod tests {
 		);
 	}
 
+	#[actix_web::test]
+	async fn test_info_url_encoded() {
+		let app_state = new_state();
+		let app =
+			test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(fertilizer_info)).await;
+		let req = test::TestRequest::get().uri("/info/Ca(NO3)2").to_request();
+		let plain = test::call_and_read_body(&app, req).await;
+		let req = test::TestRequest::get().uri("/info/Ca%28NO3%292").to_request();
+		let encoded = test::call_and_read_body(&app, req).await;
+		assert!(!plain.is_empty());
+		assert_eq!(plain, encoded);
+	}
+
+	#[actix_web::test]
+	async fn test_info_v2() {
+		let app_state = new_state();
+		let app = test::init_service(
+			App::new()
+				.app_data(web::Data::new(app_state.clone()))
+				.service(fertilizer_info_v2),
+		)
+		.await;
+		let req = test::TestRequest::get().uri("/v2/info/KNO3").to_request();
+		let resp: FertilizerInfoResponse = test::call_and_read_body_json(&app, req).await;
+		assert_eq!(resp.name, "KNO3");
+		assert!(resp.description.is_some_and(|descr| !descr.is_empty()));
+		assert!(resp.manufacturer.is_none());
+		let nitrogen = resp.components.iter().find(|elt| elt.symbol == "N").unwrap();
+		assert_delta_eq!(nitrogen.concentration, 0.1385, MOLAR_MASS_EPSILON);
+		assert_delta_eq!(nitrogen.aliases["NO3"], 0.6133, MOLAR_MASS_EPSILON);
+		let req = test::TestRequest::get().uri("/v2/info/Miracle%20Gro").to_request();
+		let mix: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+		assert_eq!(mix["name"], "Miracle Gro");
+		assert!(mix.get("description").is_none(), "{}", mix);
+
+		let req = test::TestRequest::get().uri("/v2/info/Ololo").to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	}
+
+	#[actix_web::test]
+	async fn test_best_source() {
+		let app_state = new_state();
+		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(best_source)).await;
+		let req = test::TestRequest::get().uri("/best_source?element=N").to_request();
+		let resp: BestSource = test::call_and_read_body_json(&app, req).await;
+		assert_eq!(resp.name, "Urea");
+
+		let req = test::TestRequest::get().uri("/best_source?element=Ni").to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	}
+
+	#[actix_web::test]
+	async fn test_version() {
+		let app_state = new_state();
+		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(version)).await;
+		let req = test::TestRequest::get().uri("/version").to_request();
+		let resp: VersionInfo = test::call_and_read_body_json(&app, req).await;
+		assert_eq!(resp.version, env!("CARGO_PKG_VERSION"));
+	}
+
+	#[actix_web::test]
+	async fn test_dosing_method_roundtrip() {
+		for method in [DosingMethod::Dry, DosingMethod::Solution] {
+			let json = serde_json::to_string(&method).unwrap();
+			assert_eq!(serde_json::from_str::<DosingMethod>(json.as_str()).unwrap(), method);
+		}
+	}
+
+	#[actix_web::test]
+	async fn test_info_concurrent() {
+		// Each worker runs its own actix system, so requests are processed in parallel threads
+		let app_state = new_state();
+		let workers = (0..4)
+			.map(|_| {
+				let app_state = app_state.clone();
+				std::thread::spawn(move || {
+					actix_web::rt::System::new().block_on(async move {
+						let app =
+							test::init_service(App::new().app_data(web::Data::new(app_state)).service(fertilizer_info))
+								.await;
+						for _ in 0..10 {
+							let req = test::TestRequest::get().uri("/info/KNO3").to_request();
+							let resp = test::call_service(&app, req).await;
+							assert!(resp.status().is_success());
+						}
+					})
+				})
+			})
+			.collect::<Vec<_>>();
+
+		for worker in workers {
+			worker.join().unwrap();
+		}
+	}
+
+	#[actix_web::test]
+	async fn test_mix() {
+		let app_state = new_state();
+		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(mix_info)).await;
+		let req = test::TestRequest::get().uri("/mix?n=24&p2o5=8&k2o=16").to_request();
+		let resp: Vec<ElementsConcentrationsWithAliases> = test::call_and_read_body_json(&app, req).await;
+		assert_eq!(resp.len(), 3);
+		assert_eq!(resp[0].element.name.as_ref(), "N");
+		assert_delta_eq!(resp[0].concentration, 0.24, MOLAR_MASS_EPSILON);
+	}
+
 	#[actix_web::test]
 	async fn test_calc() {
 		let app_state = new_state();
@@ -238,4 +598,122 @@ mod tests {
 		// Tank 170, target: 10ppm NO3, container: 1L, dose: 20ml
 		assert_delta_eq!(resp.compound_dose, 138.599, MOLAR_MASS_EPSILON);
 	}
+
+	#[actix_web::test]
+	async fn test_calc_malformed() {
+		let app_state = new_state();
+		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(calc)).await;
+		let mut payload = serde_json::to_value(new_calc_data_dry()).unwrap();
+		payload["dosing_data"]["type"] = "Wet".into();
+		let req = test::TestRequest::post().uri("/calc").set_json(&payload).to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+		let body = test::read_body(resp).await;
+		let body = String::from_utf8_lossy(&body);
+		assert!(body.contains("unknown variant `Wet`"), "{}", body);
+
+		// Zero tank dimensions must be rejected rather than produce infinite doses
+		let mut payload = serde_json::to_value(new_calc_data_dry()).unwrap();
+		payload["tank"] = serde_json::json!({"volume": {"height": 0, "length": 1, "width": 1}});
+		let req = test::TestRequest::post().uri("/calc").set_json(&payload).to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	}
+
+	#[actix_web::test]
+	async fn test_calc_body_limit() {
+		let app_state = new_state();
+		let app = test::init_service(
+			App::new()
+				.app_data(web::Data::new(app_state.clone()))
+				.app_data(json_config(64))
+				.service(calc),
+		)
+		.await;
+		let req = test::TestRequest::post()
+			.uri("/calc")
+			.set_json(new_calc_data_dry())
+			.to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+		let body: serde_json::Value = test::read_body_json(resp).await;
+		assert!(body["error"].as_str().is_some_and(|err| err.contains("larger than allowed")), "{}", body);
+	}
+
+	#[actix_web::test]
+	async fn test_batch_dose() {
+		let app_state = new_state();
+		let app = test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(batch_dose)).await;
+		let batch = vec![new_calc_data_dry(), new_calc_data_dry()];
+		let req = test::TestRequest::post().uri("/batch_dose").set_json(&batch).to_request();
+		let resp: DiluteResult = test::call_and_read_body_json(&app, req).await;
+		assert_delta_eq!(resp.compound_dose, 2.772 * 2.0, MOLAR_MASS_EPSILON);
+
+		let batch = vec![new_calc_data_dry(), new_calc_data_solution()];
+		let req = test::TestRequest::post().uri("/batch_dose").set_json(&batch).to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+		// Same effective volume, but a different tank definition
+		let other_tank = Tank::new_from_toml("volume = 170\nabsolute = true\n").unwrap();
+		assert_eq!(other_tank.effective_volume(), sample_tank().effective_volume());
+		let batch = vec![new_calc_data_dry(), CalcData { tank: other_tank, ..new_calc_data_dry() }];
+		let req = test::TestRequest::post().uri("/batch_dose").set_json(&batch).to_request();
+		let resp = test::call_service(&app, req).await;
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+	}
+
+	#[actix_web::test]
+	async fn test_convert_units() {
+		let app_state = new_state();
+		let result = {
+			let locked_elts = app_state.known_elements.read().unwrap();
+			let compound = compound::Compound::new("KNO3", &locked_elts).unwrap();
+			let dosing = DryDosing::with_dose(1.0);
+			dosing.dilute(&compound, &locked_elts, &sample_tank()).unwrap()
+		};
+		let app =
+			test::init_service(App::new().app_data(web::Data::new(app_state.clone())).service(convert_units)).await;
+		let data =
+			ConvertUnitsData { result, from: ConcentrationUnit::MgPerLiter, to: ConcentrationUnit::MmolPerLiter };
+		let req = test::TestRequest::post().uri("/convert_units").set_json(&data).to_request();
+		let resp: DiluteResult = test::call_and_read_body_json(&app, req).await;
+		assert_delta_eq!(resp.element_dose_by_name("N").unwrap(), 0.0582, MOLAR_MASS_EPSILON);
+	}
+
+	#[actix_web::test]
+	async fn test_run_server() {
+		let state = new_state();
+		let (server, addrs) = run_server(
+			state.db,
+			state.known_elements,
+			"127.0.0.1:0",
+			None,
+			DEFAULT_MAX_BODY_SIZE,
+			DEFAULT_LOG_FORMAT.into(),
+		)
+		.unwrap();
+		assert_eq!(addrs.len(), 1);
+		assert_ne!(addrs[0].port(), 0);
+		let handle = server.handle();
+		actix_web::rt::spawn(server);
+
+		let addr = addrs[0];
+		let response = actix_web::rt::task::spawn_blocking(move || {
+			use std::io::{Read, Write};
+			let mut stream = std::net::TcpStream::connect(addr).unwrap();
+			stream
+				.write_all(b"GET /version HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+				.unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).unwrap();
+			response
+		})
+		.await
+		.unwrap();
+		handle.stop(true).await;
+
+		assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+		assert!(response.contains(env!("CARGO_PKG_VERSION")), "{}", response);
+	}
 }
@@ -0,0 +1,39 @@
+//! Checks that machine readable outputs of the command line tool are not mixed with informational text
+
+use std::{
+	io::Write,
+	process::{Command, Stdio},
+};
+
+// Runs the tool with the specific arguments feeding the interactive input and returns its stdout
+fn run_with_input(args: &[&str], input: &str) -> String {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_fert-calc"))
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+	let output = child.wait_with_output().unwrap();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+	String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn dry_dosing_json() {
+	let stdout = run_with_input(&["--output", "json"], "KNO3\n100\n10\n");
+	let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+	assert_eq!(result["fertilizer_name"], "KNO3");
+	assert_eq!(result["compound_dose"], 10.0);
+}
+
+#[test]
+fn solution_dosing_json() {
+	let stdout = run_with_input(&["--output", "json", "--dosing-method", "solution"], "Urea\n100\n1000\n10\n10\n");
+	let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+	assert_eq!(result["compound_dose"], 10.0);
+	assert_eq!(result["stock_volume_ml"], 1000.0);
+	assert_eq!(result["portion_volume_ml"], 10.0);
+}
@@ -239,10 +239,10 @@ $(function() {
   $.getJSON("/list", function(data) {
     $.each(data, function(idx, val) {
       $('#mixSelect').append($('<option>', {
-        value: val[0],
-        text : val[0],
+        value: val.name,
+        text : val.name,
         "data-bs-toggle": "tooltip",
-        title: val[1]
+        title: val.description
       }));
     });
 
@@ -309,7 +309,7 @@ $(function() {
         dosing_data.solution_input = parseFloat($('#targetConcentration').val());
       }
       else {
-        dosing_data.dilute_input = parseFloat($('#targetConcentration').val());
+        dosing_data.target_mg_per_l = parseFloat($('#targetConcentration').val());
       }
       dosing_data.target_element = $('#targetCompound').val();
       dosing_data.what = "TargetDose";
@@ -320,7 +320,7 @@ $(function() {
         dosing_data.solution_input = parseFloat($('#addedWeight').val());
       }
       else {
-        dosing_data.dilute_input = parseFloat($('#addedWeight').val());
+        dosing_data.dose_grams = parseFloat($('#addedWeight').val());
       }
       dosing_data.what = "ResultOfDose";
     }